@@ -0,0 +1,220 @@
+#![allow(non_snake_case)]
+
+/*
+  File for rendering animations, zooms through keyframes & color cycles
+*/
+
+use crate::error::KyrosError;
+use crate::math::bigfloat::BigFloat;
+use crate::save::{get_animated_save_method, get_save_method};
+use crate::structs::{Config, MathFrame};
+use crate::utils::{color_field, eval_function, measure_field, thread_pool};
+
+/// Linear easing, the animation runs at the same pace the whole way
+fn LINEAR(t: f64) -> f64 {
+    return t;
+}
+
+fn EASE_IN(t: f64) -> f64 {
+    return t * t;
+}
+
+fn EASE_OUT(t: f64) -> f64 {
+    return 1.0 - (1.0 - t) * (1.0 - t);
+}
+
+fn EASE_IN_OUT(t: f64) -> f64 {
+    return t * t * (3.0 - 2.0 * t);
+}
+
+/// Type of every easing function, maps time in [0, 1] to progress in [0, 1]
+pub type EasingFn = dyn Fn(f64) -> f64 + Sync;
+
+/// Sets Bootleg hashmap for easing curves
+pub const EASINGS: [(&str, &EasingFn, &str);4] = [
+    ("LINEAR"      , &LINEAR      , "\tSame pace for the whole animation"),
+    ("EASE_IN"     , &EASE_IN     , "\tStarts slow & speeds up"),
+    ("EASE_OUT"    , &EASE_OUT    , "\tStarts fast & slows down"),
+    ("EASE_IN_OUT" , &EASE_IN_OUT , "Starts & ends slow (smoothstep)"),
+];
+
+/// Function for getting the easing curve from its name
+pub fn get_easing(easing: &str) -> Result<&'static EasingFn, KyrosError> {
+
+    // Tries to find function in EASINGS const
+    for (key, value, _) in EASINGS.iter() {
+        if key == &easing {
+            return Ok(value);
+        }
+    }
+
+    // If not found return error
+    return Err(KyrosError::not_found(
+        "Easing",
+        "Easings",
+        easing,
+        EASINGS.iter().map(|v| (v.0, v.2)),
+    ));
+}
+
+/// Path of the viewport through an animation
+pub struct Animation {
+    keyframes: Vec<MathFrame>, // Viewports the animation passes through, spaced evenly in time
+    frames: u64,               // Amount of frames to render
+    easing: &'static EasingFn,
+    frac_limbs: usize,         // Precision the centers are interpolated at
+}
+
+impl Animation {
+    /// Sets up an animation through at least two keyframes
+    pub fn new(keyframes: Vec<MathFrame>, frames: u64, easing: &str) -> Result<Animation, KyrosError> {
+        if keyframes.len() < 2 {
+            return Err(KyrosError::InvalidOption(format!(
+                "Animations need at least 2 keyframes, {} given!", keyframes.len()
+            )));
+        }
+        if frames == 0 {
+            return Err(KyrosError::InvalidOption("Animations need at least one frame!".to_string()));
+        }
+        for keyframe in keyframes.iter() {
+            if !(keyframe.zoom > 0.0 && keyframe.zoom.is_finite()) {
+                return Err(KyrosError::InvalidGeometry(format!(
+                    "Zoom '{}' is invalid, it must be a positive number!", keyframe.zoom
+                )));
+            }
+        }
+
+        // Enough bits to place the center within a pixel at the deepest zoom
+        let deepest = keyframes.iter().map(|v| v.zoom).fold(1.0, f64::max);
+        let frac_limbs = BigFloat::limbs_for_bits(deepest.log2() as u32 + 96);
+
+        return Ok(Animation {
+            keyframes,
+            frames,
+            easing: get_easing(easing)?,
+            frac_limbs,
+        });
+    }
+
+    pub fn frames(&self) -> u64 {
+        return self.frames;
+    }
+
+    /// Gets the viewport of frame `index`
+    pub fn frame(&self, index: u64) -> MathFrame {
+        let time = match self.frames {
+            1 => 0.0,
+            frames => index as f64 / (frames - 1) as f64,
+        };
+        let segments = self.keyframes.len() - 1;
+        let progress = (self.easing)(time.clamp(0.0, 1.0)) * segments as f64;
+
+        let segment = (progress.floor().max(0.0) as usize).min(segments - 1);
+        return self.interpolate(&self.keyframes[segment], &self.keyframes[segment + 1], progress - segment as f64);
+    }
+
+    /// Gets the viewport a fraction `t` of the way from `start` to `end`
+    fn interpolate(&self, start: &MathFrame, end: &MathFrame, t: f64) -> MathFrame {
+
+        // Zooms exponentially, so every frame magnifies by the same factor
+        let zoom = start.zoom * (end.zoom / start.zoom).powf(t);
+        let rotation = start.rotation + (end.rotation - start.rotation) * t;
+
+        // Moves the center at a steady pace on screen, which is linear in 1 / zoom.
+        // The weight is measured from the deeper keyframe, so its center keeps its precision.
+        let (deep, shallow, toward_shallow) = match end.zoom >= start.zoom {
+            true => (end, start, 1.0 - t),
+            false => (start, end, t),
+        };
+        let weight = match deep.zoom / shallow.zoom - 1.0 < 1e-9 {
+            true => toward_shallow,
+            false => ((1.0 / zoom - 1.0 / deep.zoom) / (1.0 / shallow.zoom - 1.0 / deep.zoom)).clamp(0.0, 1.0),
+        };
+
+        let (deep_re, deep_im) = self.exact_center(deep);
+        let (shallow_re, shallow_im) = self.exact_center(shallow);
+        let weight = BigFloat::from_f64(weight, self.frac_limbs);
+        let center_re = &deep_re + &(&(&shallow_re - &deep_re) * &weight);
+        let center_im = &deep_im + &(&(&shallow_im - &deep_im) * &weight);
+
+        return MathFrame {
+            center_re: center_re.to_f64(),
+            center_im: center_im.to_f64(),
+            center_exact: Some((center_re.to_decimal(), center_im.to_decimal())),
+            zoom,
+            rotation,
+        };
+    }
+
+    /// Gets the center of a keyframe at full precision
+    fn exact_center(&self, frame: &MathFrame) -> (BigFloat, BigFloat) {
+        let from_f64 = |value| BigFloat::from_f64(value, self.frac_limbs);
+        return match &frame.center_exact {
+            Some((real, imaginary)) => (
+                BigFloat::parse(real, self.frac_limbs).unwrap_or(from_f64(frame.center_re)),
+                BigFloat::parse(imaginary, self.frac_limbs).unwrap_or(from_f64(frame.center_im)),
+            ),
+            None => (from_f64(frame.center_re), from_f64(frame.center_im)),
+        };
+    }
+}
+
+/// Function for showing which frame is being rendered
+fn report_frame(config: &Config, index: u64, frames: u64) {
+    if config.progress {
+        println!("Frame {} / {}", index + 1, frames);
+    }
+}
+
+/// Renders every frame of the animation with the save method from config.
+/// Animated save methods (such as GIF) put every frame into one file, the rest save
+/// each frame on its own with `config.count` set to its index, which numbers the files.
+pub fn animate(config: &Config, animation: &Animation) -> Result<(), KyrosError> {
+
+    // An output name without the count gets it added, so the frames don't overwrite each other
+    let output = config.output.as_ref().map(|output| match output.contains("{count}") {
+        true => output.clone(),
+        false => format!("{}#{{count}}", output),
+    });
+    let frame_config = |index| Config {
+        count: config.count + index,
+        output: output.clone(),
+        math_frame: animation.frame(index),
+        ..config.clone()
+    };
+
+    if let Ok(animated_save_method) = get_animated_save_method(config.save_method.as_str()) {
+        return animated_save_method(config, animation.frames(), &|index| {
+            report_frame(config, index, animation.frames());
+            return eval_function(&frame_config(index));
+        });
+    }
+
+    let save_method = get_save_method(config.save_method.as_str())?;
+    for index in 0..animation.frames() {
+        report_frame(config, index, animation.frames());
+        save_method(&frame_config(index))?;
+    }
+    return Ok(());
+}
+
+/// Renders the image once & saves it as an animation of `frames` frames,
+/// with the hue of the color function turning a full circle over them
+pub fn color_cycle(config: &Config, frames: u64) -> Result<(), KyrosError> {
+    if frames == 0 {
+        return Err(KyrosError::InvalidOption("Animations need at least one frame!".to_string()));
+    }
+    let animated_save_method = get_animated_save_method(config.save_method.as_str())?;
+    let pool = thread_pool(config)?;
+
+    // Every frame colors the same field, so the fractal is only measured once
+    let field = measure_field(config, &pool)?;
+
+    return animated_save_method(config, frames, &|index| {
+        let frame_config = Config {
+            hue_offset: config.hue_offset + 360.0 * index as f64 / frames as f64,
+            ..config.clone()
+        };
+        return color_field(&frame_config, &pool, &field);
+    });
+}
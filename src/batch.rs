@@ -0,0 +1,187 @@
+/*
+  File for rendering many configs in one process from a batch file (TOML or .json).
+  Every job only needs the fields it changes from the defaults, such as:
+
+    [defaults]
+    size_x = 1024
+    size_y = 1024
+
+    [[jobs]]
+    output = "ship"
+    gen_formula = "BS"
+    math_frame = { center_re = -1.75, center_im = -0.03, zoom = 30.0 }
+
+    [[jobs]]
+    output = "seahorse"
+    math_frame = { center_re = -0.75, center_im = 0.1, zoom = 8.0 }
+*/
+
+use crate::config_file::{apply_table, check_fields, read_table};
+use crate::error::KyrosError;
+use crate::save::{get_save_method, saved_path};
+use crate::structs::Config;
+
+use serde_json::Value;
+
+use std::panic::{self, RefUnwindSafe};
+use std::time::Instant;
+
+/// What happened to one job of a batch
+pub struct JobReport {
+    pub count:   u64,                // Index of the job (its config.count)
+    pub output:  String,             // Where the job was saved to
+    pub seconds: f64,                // Time the job took
+    pub error:   Option<KyrosError>, // Why the job failed, if it did
+}
+
+/// Tables a batch file can have
+const BATCH_TABLES: [(&str, &str);2] = [
+    ("defaults", "Fields every job starts from"),
+    ("jobs"    , "Fields of each job, one [[jobs]] table per job"),
+];
+
+/// Function for reading the config of every job in a batch file.
+/// Jobs start from `base`, then the [defaults] table, then their own table
+/// & are given their index (from `base.count`) as count, unless they set one.
+/// A job that can't be read is returned as its error, without stopping the others.
+pub fn read_batch_file(path: &str, base: &Config) -> Result<Vec<Result<Config, KyrosError>>, KyrosError> {
+    let invalid = |message: &str| KyrosError::InvalidFile { path: path.to_string(), message: message.to_string() };
+    let mut table = read_table(path)?;
+    check_fields(&table, "Batch table", "Batch tables", &BATCH_TABLES)?;
+
+    let defaults = match table.get_mut("defaults").map(Value::take) {
+        Some(defaults @ Value::Object(_)) => defaults,
+        Some(_) => return Err(invalid("its defaults must be a table")),
+        None => Value::Object(Default::default()),
+    };
+    let jobs = match table.get_mut("jobs").map(Value::take) {
+        Some(Value::Array(jobs)) if !jobs.is_empty() => jobs,
+        _ => return Err(invalid("it has no [[jobs]] tables")),
+    };
+
+    return Ok(jobs
+        .into_iter()
+        .enumerate()
+        .map(|(index, job)| {
+            let indexed = Config { count: base.count + index as u64, ..base.clone() };
+            let defaulted = apply_table(path, &indexed, defaults.clone())?;
+            return apply_table(path, &defaulted, job);
+        })
+        .collect());
+}
+
+/// Renders & saves every job of a batch file, in order.
+/// Jobs that fail (or panic) are reported & skipped, the rest are still rendered.
+pub fn run_batch(path: &str, base: &Config) -> Result<Vec<JobReport>, KyrosError> {
+    let jobs = read_batch_file(path, base)?;
+
+    // Every job with the same amount of threads renders on the same thread pool
+    return Ok(run_jobs(jobs, base, &|config| get_save_method(config.save_method.as_str())?(config)));
+}
+
+/// Runs every job that could be read with `run`, a job that panics only fails itself
+fn run_jobs(
+    jobs: Vec<Result<Config, KyrosError>>,
+    base: &Config,
+    run: &(dyn Fn(&Config) -> Result<(), KyrosError> + RefUnwindSafe),
+) -> Vec<JobReport> {
+    let total = jobs.len();
+    let mut reports = Vec::with_capacity(total);
+
+    for (index, job) in jobs.into_iter().enumerate() {
+        let start = Instant::now();
+        let (count, output) = match &job {
+            Ok(config) => (config.count, saved_path(config)),
+            Err(_) => (base.count + index as u64, "-".to_string()),
+        };
+        if base.progress {
+            println!("Job {} / {}: {}", index + 1, total, output);
+        }
+
+        let result = job.and_then(|config| {
+            return panic::catch_unwind(|| run(&config)).unwrap_or_else(|payload| Err(KyrosError::panicked(payload)));
+        });
+        if let Err(error) = &result {
+            eprintln!("Job {} failed: {}\n", count, error);
+        }
+
+        reports.push(JobReport {
+            count,
+            output,
+            seconds: start.elapsed().as_secs_f64(),
+            error: result.err(),
+        });
+    }
+    return reports;
+}
+
+/// Function for writing the reports of a batch as a table
+pub fn batch_summary(reports: &[JobReport]) -> String {
+    let mut summary = format!("{:>5}  {:<6}  {:>9}  {}\n", "Job", "Status", "Time", "Output");
+    for report in reports.iter() {
+        let status = match report.error {
+            Some(_) => "failed",
+            None => "done",
+        };
+        summary += &format!("{:>5}  {:<6}  {:>8.2}s  {}\n", report.count, status, report.seconds, report.output);
+    }
+
+    let failed = reports.iter().filter(|report| report.error.is_some()).count();
+    let seconds: f64 = reports.iter().map(|report| report.seconds).sum();
+    summary += &format!("{} jobs in {:.2}s, {} failed\n", reports.len(), seconds, failed);
+    return summary;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::temp_path;
+
+    use std::fs;
+
+    #[test]
+    fn misspelled_fields_fail_their_job() {
+        let path = temp_path("misspelled.toml");
+        fs::write(&path, "[defaults]\nmax_i = 50\n\n[[jobs]]\nsize_x = 16\n\n[[jobs]]\nmax_it = 3\n").unwrap();
+        let jobs = read_batch_file(&path, &Config::default()).unwrap();
+
+        fs::write(&path, "[defaults]\nmax_i = 50\n\n[[job]]\nsize_x = 16\n").unwrap();
+        let misspelled_table = read_batch_file(&path, &Config::default());
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(jobs.len(), 2);
+        assert_eq!(jobs[0].as_ref().unwrap().max_i, 50);
+        assert_eq!(jobs[1].as_ref().err().unwrap().suggestion(), Some("max_i"));
+        assert_eq!(misspelled_table.err().unwrap().suggestion(), Some("jobs"));
+    }
+    #[test]
+    fn failing_jobs_dont_stop_the_batch() {
+        let path = temp_path("failing.toml");
+        let output = temp_path("failing-{count}");
+        fs::write(&path, format!(
+            "[defaults]\nsize_x = 16\nsize_y = 16\noutput = '{}'\n\n[[jobs]]\n\n[[jobs]]\ngen_formula = 'SDD'\n\n\
+            [[jobs]]\nsize_x = 4000000000\nsize_y = 4000000000\n\n[[jobs]]\nmax_i = 8\n",
+            output,
+        )).unwrap();
+        let reports = run_batch(&path, &Config::default()).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        let errors: Vec<Option<u8>> = reports.iter().map(|report| report.error.as_ref().map(KyrosError::exit_code)).collect();
+        assert_eq!(errors, [None, Some(2), Some(2), None]);
+        for report in reports.iter().filter(|report| report.error.is_none()) {
+            fs::remove_file(&report.output).unwrap();
+        }
+    }
+
+    #[test]
+    fn panicking_jobs_only_fail_themselves() {
+        let jobs = (0..3).map(|count| Ok(Config { count, ..Config::default() })).collect();
+        let reports = run_jobs(jobs, &Config::default(), &|config| {
+            assert!(config.count != 1, "job {} panicked", config.count);
+            return Ok(());
+        });
+
+        let errors: Vec<Option<String>> = reports.iter().map(|report| report.error.as_ref().map(KyrosError::to_string)).collect();
+        assert_eq!(errors, [None, Some("The render crashed, which is a bug: job 1 panicked".to_string()), None]);
+    }
+}
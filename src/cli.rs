@@ -7,14 +7,14 @@ Author : Mark T
   File for storing CLI Configuration
 */
 
-use clap::Parser;
+use clap::{Parser, Subcommand};
 
 static ABOUT_CLI_ARGS: &str = "
  ~ Kyros
 A CLI tool for generating fractal images.
 
 Example:
-  kyros --pixels 512 --formula R --color ROTATIONAL --shadow MINIMAL --travel-distance --progress -y
+  kyros --pixels 512 --formula R --color ROTATIONAL --shadow MINIMAL --measure TRAVEL_DISTANCE --progress -y
 ";
 
 
@@ -27,18 +27,97 @@ kyros --pixels 512       \\
       --formula R        \\
       --color ROTATIONAL \\
       --shadow MINIMAL   \\
-      --travel-distance  \\
+      --measure TRAVEL_DISTANCE \\
       --progress         \\
       -y
 
 The 'pixels' flag refers to the size of the image, (both the amount of pixels in the x & y direction.)
+The 'width' & 'height' flags set the size of the image in each direction separately.
+The 'center-re', 'center-im', 'zoom' & 'rotation' flags set the part of the plane that is shown.
+Zooms past f64 precision (around 1e13) switch to a perturbation renderer automatically (or always with 'deep'.)
+  Only SD, R, ABR, BS & SYM have one, other formulas & formula expressions give an error that deep.
 The 'formula' flag refers to the formula that is used to get a value to pass to the color generation. 
-The 'color' flag refers to the formula that generates a hue value.
+The 'formula-expr' flag replaces the formula with an expression of z & c, (with + - * / ^, abs, conj, re, im, exp, sin, etc.)
+The 'polynomial' flag sets the polynomial the NEWTON & NOVA formulas find the roots of, (color them with the ROOT measurement & SPEED shadow.)
+The 'color' flag refers to the formula that generates a hue value, (turned by 'hue-offset' degrees.)
+  Colormaps (VIRIDIS, MAGMA, INFERNO & TWILIGHT) are picked with the 'color' flag too.
+The 'normalize' flag sets how values are put along the colormap or hue wheel, (CYCLE repeats it, LINEAR, LOG & HISTOGRAM
+  stretch the values of the whole image over it once, HISTOGRAM spreads the colors evenly at any zoom or iterations.)
+The 'palette' flag colors with a gradient file in place of the hue wheel, (moved along by 'palette-offset' & repeated 'palette-scale' times.)
+  Kyros palettes have a stop on every line, written as an optional position (0 to 1) & a color, such as '0.5 #206BCB'.
+  GIMP gradients (.ggr) & Fractint maps (.map) are read too.
 The 'shadow' flag refers to the formula that generates the light / dark value of each pixel.
-The 'travel-distance' flag changes the measurement of the generator to instead of measuring iterations, measuring mathematical travel distance.
+The 'interior' flag colors the orbits that never escape, (by their final |z|, or the period, average angle or multiplier of the cycle they settle into.)
+The 'instant-escape' flag sets the color of pixels that escape before the first step, (WHITE, BLACK, GRAY, COLOR or any '#RRGGBB'.)
+The 'measure' flag refers to the way each orbit is turned into the value that is colored, (iterations, travel distance, etc.)
+  DISTANCE estimates the distance of each pixel to the boundary of the set from dz/dc, draw it as lines with the BOUNDARY shadow.
+  Formulas without an analytic derivative (& formula expressions) take it numerically. DISTANCE_RAW gives the distance itself.
+The 'band-rows' flag sets how many rows the STREAM save method holds in memory at once.
+The 'bailout' flag sets the escape radius, each formula has its own default (raised to 256 for measurements that need a large one, such as SMOOTH, which need it to be larger than 1.) Deep zooms take one of at most 1e9.
+The 'escape-norm' flag sets the test for escaping orbits, (|z|, |re z|, |im z|, |re z| + |im z|, or converging for convergent fractals.)
+The 'threads' flag sets the amount of threads the image is rendered with, (0 uses every core.)
+
+Animations:
+The 'animate' subcommand renders numbered frames (out#0.png, out#1.png, ...) zooming from the viewport set
+by the flags above to the one set by its 'end-*' flags, or through every 'keyframe' given.
+  kyros -p 512 -y animate --frames 120 --end-center-re -0.75 --end-center-im 0.1 --end-zoom 1000 --easing EASE_IN_OUT
+With the GIF or APNG save methods every frame is put into one file instead, played at 'fps' frames per second.
+The 'cycle' subcommand renders the image once & turns its hue a full circle, saved as a GIF or APNG.
+  kyros -p 512 --save-method GIF -y cycle --frames 60
+
+Recoloring:
+The KYF & NPY save methods save the measured value of every pixel instead of an image, the 'recolor'
+subcommand colors such a file again without redoing the measurements.
+  kyros -p 2048 --save-method KYF -y
+  kyros --color SINUSOIDAL --shadow MINIMAL -y recolor out#0.kyf
+
+Reproducing:
+Every PNG stores the config it was rendered with, the 'reproduce' subcommand renders it again (into the
+first out#N.png that doesn't exist yet), optionally at a different resolution. Every other render saves to
+out#0.png (or its 'output' name) & replaces the image from the run before, keep one by renaming it.
+  kyros -y reproduce out#0.png --scale 4
+
+Julia sets:
+The 'julia' flag renders the Julia set of any formula, with the constant set by 'julia-c' (or in polar form
+by 'julia-c-polar', with the angle in degrees.) The 'julia-grid' subcommand renders a grid of small Julia sets
+into one image, each tile the size set by the flags above, with constants sampled across a region of the
+parameter plane.
+  kyros --julia-c -0.8,0.156 -p 1024 -i 2048 -y
+  kyros -p 128 -y julia-grid --columns 12 --rows 8 --c-center-re -0.5 --c-zoom 1.5
+
+Density renders:
+The 'buddhabrot' subcommand samples random points from [-2, 2] on both axes & counts every point the orbits of
+the escaping ones pass through, in the viewport set by the flags above. Each 'band' of escape times is counted
+on its own, three bands are mapped to red, green & blue (a Nebulabrot.)
+  kyros -p 1024 -y buddhabrot --samples 50000000 --band 0,5000 --band 0,500 --band 0,50 --tone-map LOG
+
+Batches:
+The 'batch' subcommand renders every [[jobs]] table of a file in one process, each only needs the fields
+it changes from the [defaults] table (which changes the config set by the flags.) Jobs are given their
+index as count (so out#0.png, out#1.png, ... unless they set an 'output' name.) A job that fails doesn't
+stop the others, a table of every job is shown at the end.
+  kyros -p 1024 -y batch jobs.toml
+
+Config files:
+The 'config' flag reads the config from a TOML file (or a JSON file ending in .json), which only needs the
+fields it changes (fields it doesn't know, such as misspelled ones, are errors.) Flags given on the command
+line override the file. The 'dump-config' flag prints the config as TOML instead of generating the image,
+which makes a good starting point for a file.
+  kyros -p 1024 --formula BS --zoom 30 --dump-config > burning_ship.toml
+  kyros --config burning_ship.toml --iterations 4096 -y
 
 Getting more help:
-Potential values for the formula, color and shadow flags can be retreived by passing an invalid values (such as 'HELP') to them.
+Potential values for the formula, measure, escape-norm, color, normalize, shadow, interior and instant-escape flags can be retreived by passing an invalid values (such as 'HELP') to them.
+
+Exit codes:
+  0  The image was generated
+  2  Invalid arguments, configuration or input file
+  3  A file (or std-out) couldn't be written
+  4  The image couldn't be encoded
+  5  Some of the jobs of a batch failed
+  6  The worker threads couldn't be started
+  7  The render crashed (a bug)
+  8  An option is out of range or can't be used with the rest of the config
 ";
 
 #[derive(Parser, Debug)]
@@ -47,10 +126,34 @@ Potential values for the formula, color and shadow flags can be retreived by pas
 #[command(version)]
 pub struct Args {
 
-    /// The amount of pixels to generate
+    /// The amount of pixels to generate (both width & height)
     #[arg(short, long, default_value_t = 256, value_name="INT")]
     pub pixels: u32,
 
+    /// The width of the image in pixels (overrides 'pixels')
+    #[arg(long, value_name="INT")]
+    pub width: Option<u32>,
+
+    /// The height of the image in pixels (overrides 'pixels')
+    #[arg(long, value_name="INT")]
+    pub height: Option<u32>,
+
+    /// The real value at the center of the image (digits past f64 precision are kept for deep zooms)
+    #[arg(long, default_value_t=("0.0".to_string()), value_name="FLOAT", allow_hyphen_values=true)]
+    pub center_re: String,
+
+    /// The imaginary value at the center of the image (digits past f64 precision are kept for deep zooms)
+    #[arg(long, default_value_t=("0.0".to_string()), value_name="FLOAT", allow_hyphen_values=true)]
+    pub center_im: String,
+
+    /// The magnification of the image (1.0 shows [-2, 2] on the shorter side)
+    #[arg(short, long, default_value_t = 1.0, value_name="FLOAT")]
+    pub zoom: f64,
+
+    /// The rotation of the image around its center in degrees
+    #[arg(long, default_value_t = 0.0, value_name="FLOAT", allow_negative_numbers=true)]
+    pub rotation: f64,
+
     /// The amount of iterations to run per pixel
     #[arg(short, long, default_value_t = 1024, value_name="INT")]
     pub iterations: u64,
@@ -59,10 +162,42 @@ pub struct Args {
     #[arg(short, long, default_value_t=("SD".to_string()), value_name="STR", long_help="Sets the generation function to use. \nSet this value to 'HELP' for more information.")] // The Compiler lies, parentheses are needed
     pub formula: String,
 
+    /// A formula written as an expression of z & c, such as "z^3 + c - conj(z)*0.2" (used in place of 'formula')
+    #[arg(long, value_name="STR", conflicts_with="formula", allow_hyphen_values=true)]
+    pub formula_expr: Option<String>,
+
+    /// The polynomial of Newton style formulas (NEWTON & NOVA) as its coefficients from the highest power down, such as "1,0,0,-1" for z^3 - 1 (NOVA still starts at z = 1)
+    #[arg(long, value_name="FLOAT,...", allow_hyphen_values=true)]
+    pub polynomial: Option<String>,
+
+    /// The power n of the MULTIBROT formula, z^n + c, from 2 to 64 (only with MULTIBROT, sets its polynomial to z^n)
+    #[arg(long, value_name="INT", conflicts_with="polynomial", value_parser=clap::value_parser!(u32).range(2..=64))]
+    pub power: Option<u32>,
+
     /// Specifies color function to use
     #[arg(long, default_value_t=("ROTATIONAL".to_string()), value_name="STR")]
     pub color: String,
 
+    /// Degrees added to the hue of the color function
+    #[arg(long, default_value_t = 0.0, value_name="FLOAT", allow_negative_numbers=true)]
+    pub hue_offset: f64,
+
+    /// A gradient file (.ggr, .map or a Kyros palette) used in place of the hue wheel
+    #[arg(long, value_name="FILE")]
+    pub palette: Option<String>,
+
+    /// The fraction of the palette it is moved along by
+    #[arg(long, default_value_t = 0.0, value_name="FLOAT", allow_negative_numbers=true)]
+    pub palette_offset: f64,
+
+    /// The amount of times the palette repeats over one turn of the hue
+    #[arg(long, value_name="FLOAT")]
+    pub palette_scale: Option<f64>,
+
+    /// The way values are put along the colormap or hue wheel (CYCLE, LINEAR, LOG or HISTOGRAM)
+    #[arg(long, value_name="STR")]
+    pub normalize: Option<String>,
+
     /// Specifies shadow function to use
     #[arg(long, default_value_t=("NONE".to_string()), value_name="STR")]
     pub shadow: String,
@@ -71,19 +206,220 @@ pub struct Args {
     #[arg(long, default_value_t=("PNG".to_string()), value_name="STR")]
     pub save_method: String,
 
-    /// Uses Julia set style generation
+    /// The name to save the image as, without its extension ('{count}' is replaced by the image index)
+    #[arg(short, long, value_name="STR")]
+    pub output: Option<String>,
+
+    /// The amount of rows the STREAM save method renders at a time
+    #[arg(long, default_value_t = 256, value_name="INT")]
+    pub band_rows: u32,
+
+    /// The frames per second of animations saved with GIF or APNG
+    #[arg(long, default_value_t = 24, value_name="INT")]
+    pub fps: u16,
+
+    /// Uses Julia set style generation (with c = 0.0800 - 0.6359i unless 'julia-c' or 'julia-c-polar' is set)
     #[arg(short, long, default_value_t=false, value_name="BOOL")]
     pub julia: bool,
 
-    /// Uses Travel Distance to color pixels
-    #[arg(long, default_value_t=false, value_name="BOOL")]
+    /// The constant of the Julia set to generate
+    #[arg(long, value_name="RE,IM", allow_hyphen_values=true)]
+    pub julia_c: Option<String>,
+
+    /// The constant of the Julia set to generate in polar form, with the angle in degrees
+    #[arg(long, value_name="R,THETA", allow_hyphen_values=true, conflicts_with="julia_c")]
+    pub julia_c_polar: Option<String>,
+
+    /// Specifies the measurement that turns each orbit into a value
+    #[arg(short, long, default_value_t=("ITERATIONS".to_string()), value_name="STR")]
+    pub measure: String,
+
+    /// Deprecated, the same as '--measure TRAVEL_DISTANCE'
+    #[arg(long, default_value_t=false, value_name="BOOL", hide=true, conflicts_with_all=["measure", "smooth"])]
     pub travel_distance: bool,
 
+    /// Deprecated, the same as '--measure SMOOTH'
+    #[arg(long, default_value_t=false, value_name="BOOL", hide=true, conflicts_with="measure")]
+    pub smooth: bool,
+
+    /// Uses the perturbation renderer even when the zoom isn't deep enough to need it
+    #[arg(long, default_value_t=false, value_name="BOOL")]
+    pub deep: bool,
+
+    /// The amount of threads to render with (0 uses every core)
+    #[arg(short, long, default_value_t = 0, value_name="INT")]
+    pub threads: usize,
+
+    /// The escape radius (defaults to the one of the formula, at least 256 for measurements that need a large one such as SMOOTH)
+    #[arg(long, value_name="FLOAT")]
+    pub bailout: Option<f64>,
+
+    /// The test for escaping orbits (defaults to the one of the formula)
+    #[arg(long, value_name="STR", long_help="Sets the test for escaping orbits (defaults to the one of the formula). \nSet this value to 'HELP' for more information.")]
+    pub escape_norm: Option<String>,
+
+    /// The way orbits that never escape are colored (BLACK by default)
+    #[arg(long, value_name="STR", long_help="Sets the way orbits that never escape are colored (BLACK by default). \nSet this value to 'HELP' for more information.")]
+    pub interior: Option<String>,
+
+    /// The color of pixels that escape before the first step, a name or #RRGGBB (WHITE by default)
+    #[arg(long, value_name="STR")]
+    pub instant_escape: Option<String>,
+
     /// Flag for showing progress
     #[arg(long, default_value_t=false, value_name="BOOL")]
     pub progress: bool,
 
+    /// A TOML (or .json) file to read the config from, flags given on the command line override it
+    #[arg(long, value_name="FILE")]
+    pub config: Option<String>,
+
+    /// Prints the config as TOML (usable with 'config') instead of generating the image
+    #[arg(long, default_value_t=false, value_name="BOOL")]
+    pub dump_config: bool,
+
     /// Confirm image generation
-    #[arg(short, long, required(true))]
+    #[arg(short, long, required_unless_present("dump_config"))]
     pub y_confirm: bool,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Renders a zoom animation from the viewport set above to an end viewport (or through keyframes)
+    Animate(AnimateArgs),
+    /// Renders the image once & turns the hue a full circle over an animation (GIF or APNG)
+    Cycle(CycleArgs),
+    /// Colors a field saved with the KYF or NPY save methods, with the color, shadow & hue-offset flags
+    Recolor(RecolorArgs),
+    /// Renders an image saved by Kyros again, from the config stored in the PNG
+    Reproduce(ReproduceArgs),
+    /// Renders a grid of Julia sets, with constants sampled across a region of the parameter plane, into one image
+    JuliaGrid(JuliaGridArgs),
+    /// Renders the density of the orbits of random escaping points (a Buddhabrot, or a Nebulabrot with three bands)
+    Buddhabrot(BuddhabrotArgs),
+    /// Renders every job of a batch file (TOML or .json), each starting from the config set above
+    Batch(BatchArgs),
+}
+
+#[derive(clap::Args, Debug)]
+pub struct AnimateArgs {
+
+    /// The amount of frames to render
+    #[arg(long, default_value_t = 60, value_name="INT")]
+    pub frames: u64,
+
+    /// The real value at the center of the last frame (defaults to 'center-re')
+    #[arg(long, value_name="FLOAT", allow_hyphen_values=true)]
+    pub end_center_re: Option<String>,
+
+    /// The imaginary value at the center of the last frame (defaults to 'center-im')
+    #[arg(long, value_name="FLOAT", allow_hyphen_values=true)]
+    pub end_center_im: Option<String>,
+
+    /// The magnification of the last frame (defaults to 'zoom')
+    #[arg(long, value_name="FLOAT")]
+    pub end_zoom: Option<f64>,
+
+    /// The rotation of the last frame in degrees (defaults to 'rotation')
+    #[arg(long, value_name="FLOAT", allow_negative_numbers=true)]
+    pub end_rotation: Option<f64>,
+
+    /// A viewport the animation passes through, can be given many times (used in place of the start & end viewports)
+    #[arg(long="keyframe", value_name="RE,IM,ZOOM[,ROTATION]", allow_hyphen_values=true)]
+    pub keyframes: Vec<String>,
+
+    /// The easing curve of the animation
+    #[arg(long, default_value_t=("LINEAR".to_string()), value_name="STR")]
+    pub easing: String,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct CycleArgs {
+
+    /// The amount of frames to render
+    #[arg(long, default_value_t = 60, value_name="INT")]
+    pub frames: u64,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct RecolorArgs {
+
+    /// The .kyf or .npy file to color
+    #[arg(value_name="FILE")]
+    pub file: String,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct ReproduceArgs {
+
+    /// The PNG to render again
+    #[arg(value_name="FILE")]
+    pub file: String,
+
+    /// Multiplies the width & height of the stored image (the viewport stays the same)
+    #[arg(long, value_name="FLOAT")]
+    pub scale: Option<f64>,
+
+    /// The width to render at (keeps the aspect ratio when 'height' isn't set)
+    #[arg(long, value_name="INT")]
+    pub width: Option<u32>,
+
+    /// The height to render at (keeps the aspect ratio when 'width' isn't set)
+    #[arg(long, value_name="INT")]
+    pub height: Option<u32>,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct BatchArgs {
+
+    /// The file listing the jobs to render
+    #[arg(value_name="FILE")]
+    pub file: String,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct JuliaGridArgs {
+
+    /// The amount of tiles across the image
+    #[arg(long, default_value_t = 8, value_name="INT")]
+    pub columns: u32,
+
+    /// The amount of tiles down the image
+    #[arg(long, default_value_t = 8, value_name="INT")]
+    pub rows: u32,
+
+    /// The real value at the center of the region the constants are sampled from
+    #[arg(long, default_value_t = -0.5, value_name="FLOAT", allow_negative_numbers=true)]
+    pub c_center_re: f64,
+
+    /// The imaginary value at the center of the region the constants are sampled from
+    #[arg(long, default_value_t = 0.0, value_name="FLOAT", allow_negative_numbers=true)]
+    pub c_center_im: f64,
+
+    /// The magnification of the region the constants are sampled from (1.0 spans [-2, 2] on the shorter side)
+    #[arg(long, default_value_t = 1.5, value_name="FLOAT")]
+    pub c_zoom: f64,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct BuddhabrotArgs {
+
+    /// The amount of random points to run orbits from
+    #[arg(long, default_value_t = 10_000_000, value_name="INT")]
+    pub samples: u64,
+
+    /// The seed of the random points (the same seed gives the same image)
+    #[arg(long, default_value_t = 0, value_name="INT")]
+    pub seed: u64,
+
+    /// The escape times counted, given up to three times for red, green & blue (defaults to 0,ITERATIONS)
+    #[arg(long="band", value_name="MIN,MAX")]
+    pub bands: Vec<String>,
+
+    /// The tone map turning counts into brightness
+    #[arg(long, default_value_t=("SQRT".to_string()), value_name="STR")]
+    pub tone_map: String,
 }
@@ -1,9 +1,8 @@
 #![allow(non_snake_case)]
 
-use super::super::Args;
-
-use clap::error::ErrorKind;
-use clap::CommandFactory;
+use crate::colors::colormap::COLORMAPS;
+use crate::colors::palette::parse_hex;
+use crate::error::KyrosError;
 
 /*
     Author : Mark T
@@ -30,30 +29,69 @@ fn SINUSOIDAL(n: f64) -> f64 {
         (max_value + min_value) * 0.5;
 }
 
-const COLORS: [(&str, &dyn Fn(f64) -> f64, &str);2] = [
+/// Type of every color function
+pub type ColorFn = dyn Fn(f64) -> f64 + Sync;
+
+pub const COLORS: [(&str, &ColorFn, &str);2] = [
     ("ROTATIONAL", &ROTATIONAL, "Simple rotational color based on iteration value"),
     ("SINUSOIDAL", &SINUSOIDAL, "Sinusoidal color values generated between set values"),
 ];
 
-/// Function for getting the color formula from config
-pub fn get_color(color: &str) -> &dyn Fn(f64) -> f64 {
+/// Function for getting the color formula from config.
+/// Colormaps are picked with the same names, so they are listed with the color functions.
+pub fn get_color(color: &str) -> Result<&'static ColorFn, KyrosError> {
 
     // Tries to find function in FORMULAS const
     for (key, value, _) in COLORS.iter() {
         if key == &color {
-            return value;
+            return Ok(value);
         }
     }
 
-    let color_string: String = COLORS
-        .iter()
-        .map(|v| format!("  {}\t{}", v.0, v.2))
-        .collect::<Vec<String>>()
-        .join("\n");
-
-    // If not found throw error
-    Args::command().error(
-        ErrorKind::InvalidValue,
-        format!("Color generation method '{}' not found!\n\nAllowed Colors:\n{}", color, color_string)
-    ).exit();
+    // If not found return error
+    return Err(KyrosError::not_found(
+        "Color generation method",
+        "Colors",
+        color,
+        COLORS.iter().map(|v| (v.0, v.2)).chain(COLORMAPS.iter().map(|v| (v.name, v.description))),
+    ));
+}
+
+/// Color of the pixels that escape before the first step
+#[derive(Debug, Clone, Copy)]
+pub enum InstantEscape {
+    Rgb(u8, u8, u8),
+    Color, // Colored like every other escaped pixel
+}
+
+/// Sets Bootleg hashmap for the colors of instant escapes
+pub const INSTANT_ESCAPES: [(&str, InstantEscape, &str);4] = [
+    ("WHITE", InstantEscape::Rgb(255, 255, 255), "\tPaints them white"),
+    ("BLACK", InstantEscape::Rgb(0, 0, 0)      , "\tPaints them black"),
+    ("GRAY" , InstantEscape::Rgb(128, 128, 128), "\tPaints them gray"),
+    ("COLOR", InstantEscape::Color             , "\tColors them like every other escaped pixel"),
+];
+
+/// Function for getting the color of instant escapes from config, a name or a color written as #RRGGBB (WHITE when not set)
+pub fn get_instant_escape(instant_escape: Option<&str>) -> Result<InstantEscape, KyrosError> {
+    let instant_escape = instant_escape.unwrap_or("WHITE");
+
+    // Tries to find color in INSTANT_ESCAPES const
+    for (key, value, _) in INSTANT_ESCAPES.iter() {
+        if key == &instant_escape {
+            return Ok(*value);
+        }
+    }
+    if let Some(rgb) = parse_hex(instant_escape) {
+        let [r, g, b] = rgb.map(|v| (255.0 * v).round() as u8);
+        return Ok(InstantEscape::Rgb(r, g, b));
+    }
+
+    // If not found return error
+    return Err(KyrosError::not_found(
+        "Instant escape color",
+        "Instant escape colors",
+        instant_escape,
+        INSTANT_ESCAPES.iter().map(|v| (v.0, v.2)).chain([("#RRGGBB", "Paints them any color written in hex")]),
+    ));
 }
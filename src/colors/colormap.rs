@@ -0,0 +1,313 @@
+#![allow(non_snake_case)]
+
+/*
+  File for the built-in colormaps, perceptually uniform gradients (from matplotlib) that are picked
+  with the color flag like the color functions. TWILIGHT is an approximation of matplotlib's twilight,
+  blended from a few stops, so it is close to but not the same as the original.
+
+  The value of every pixel is put in [0, 1] by a normalization first, which is picked separately from the map:
+
+    CYCLE (default) repeats the map every 40 units of the value, like one turn of ROTATIONAL.
+    LINEAR, LOG & HISTOGRAM stretch the values of the whole image over the map once.
+
+  Normalizations work with the color functions too, where [0, 1] is one turn of ROTATIONAL.
+  HISTOGRAM is histogram equalization, every pixel is colored by its rank among the values of
+  the image, so the colors are spread evenly whatever the zoom or amount of iterations.
+*/
+
+use crate::colors::palette::{oklab_to_srgb, srgb_to_oklab};
+use crate::error::KyrosError;
+use crate::math::measurement::HUE_CYCLE;
+
+/// Evaluates a polynomial fit of a colormap, with its coefficients from the lowest power up
+fn polynomial_fit(coefficients: &[[f64;3];7], t: f64) -> [f64;3] {
+    let t = t.clamp(0.0, 1.0);
+    return [0, 1, 2].map(|i| coefficients.iter().rev().fold(0.0, |value, c| value * t + c[i]));
+}
+
+/// Polynomial fits of matplotlib's viridis, magma & inferno (by Matt Zucker, CC0)
+const VIRIDIS_FIT: [[f64;3];7] = [
+    [0.2777273272234177, 0.005407344544966578, 0.3340998053353061],
+    [0.1050930431085774, 1.404613529898575, 1.384590162594685],
+    [-0.3308618287255563, 0.214847559468213, 0.09509516302823659],
+    [-4.634230498983486, -5.799100973351585, -19.33244095627987],
+    [6.228269936347081, 14.17993336680509, 56.69055260068105],
+    [4.776384997670288, -13.74514537774601, -65.35303263337234],
+    [-5.435455855934631, 4.645852612178535, 26.3124352495832],
+];
+
+const MAGMA_FIT: [[f64;3];7] = [
+    [-0.002136485053939582, -0.000749655052795221, -0.005386127855323933],
+    [0.2516605407371642, 0.6775232436837668, 2.494026599312351],
+    [8.353717279216625, -3.577719514958484, 0.3144679030132573],
+    [-27.66873308576866, 14.26473078096533, -13.64921318813922],
+    [52.17613981234068, -27.94360607168351, 12.94416944238394],
+    [-50.76852536473588, 29.04658282127291, 4.23415299384598],
+    [18.65570506591883, -11.48977351997711, -5.601961508734096],
+];
+
+const INFERNO_FIT: [[f64;3];7] = [
+    [0.0002189403691192265, 0.001651004631001012, -0.01948089843709184],
+    [0.1065134194856116, 0.5639564367884091, 3.932712388889277],
+    [11.60249308247187, -3.972853965665698, -15.9423941062914],
+    [-41.70399613139459, 17.43639888205313, 44.35414519872813],
+    [77.162935699427, -33.40235894210092, -81.80730925738993],
+    [-71.31942824499214, 32.62606426397723, 73.20951985803202],
+    [25.13112622477341, -12.24266895238567, -23.07032500287172],
+];
+
+/// Hand-picked sRGB stops approximating matplotlib's twilight (not samples of its table),
+/// the last one is the first again so the map wraps
+const TWILIGHT_STOPS: [[f64;3];9] = [
+    [0.886, 0.850, 0.888],
+    [0.659, 0.706, 0.809],
+    [0.384, 0.510, 0.749],
+    [0.325, 0.255, 0.588],
+    [0.184, 0.078, 0.216],
+    [0.463, 0.145, 0.302],
+    [0.690, 0.337, 0.298],
+    [0.808, 0.612, 0.541],
+    [0.886, 0.850, 0.888],
+];
+
+fn VIRIDIS(t: f64) -> [f64;3] {
+    return polynomial_fit(&VIRIDIS_FIT, t);
+}
+
+fn MAGMA(t: f64) -> [f64;3] {
+    return polynomial_fit(&MAGMA_FIT, t);
+}
+
+fn INFERNO(t: f64) -> [f64;3] {
+    return polynomial_fit(&INFERNO_FIT, t);
+}
+
+/// Blends between the stops approximating twilight in OKLab
+fn TWILIGHT(t: f64) -> [f64;3] {
+    let position = t.rem_euclid(1.0) * (TWILIGHT_STOPS.len() - 1) as f64;
+    let index = (position as usize).min(TWILIGHT_STOPS.len() - 2);
+    let fraction = position - index as f64;
+
+    let start = srgb_to_oklab(TWILIGHT_STOPS[index]);
+    let end = srgb_to_oklab(TWILIGHT_STOPS[index + 1]);
+    return oklab_to_srgb([0, 1, 2].map(|i| start[i] + (end[i] - start[i]) * fraction));
+}
+
+/// Type of every colormap, `position in [0, 1] -> sRGB color in [0, 1]`
+pub type ColormapFn = dyn Fn(f64) -> [f64;3] + Sync;
+
+/// Entry of the COLORMAPS table
+pub struct Colormap {
+    pub name: &'static str,
+    pub function: &'static ColormapFn,
+    pub cyclic: bool, // Both ends are the same color, so positions past 1 wrap around instead of stopping
+    pub description: &'static str,
+}
+
+impl Colormap {
+    /// Gets the color at a position from a normalization, at a brightness in [0, 1].
+    /// Positions wrap for cyclic maps & the CYCLE normalization, otherwise they stop at the ends.
+    pub fn color(&self, position: f64, wrap: bool, brightness: f64) -> (u8, u8, u8) {
+        let position = match wrap || self.cyclic {
+            true => position.rem_euclid(1.0),
+            false => position.clamp(0.0, 1.0),
+        };
+        let rgb = (self.function)(position).map(|v| (255.0 * (v * brightness).clamp(0.0, 1.0)).round() as u8);
+        return (rgb[0], rgb[1], rgb[2]);
+    }
+}
+
+/// Sets Bootleg hashmap for colormaps
+pub const COLORMAPS: [Colormap;4] = [
+    Colormap { name: "VIRIDIS" , function: &VIRIDIS , cyclic: false, description: "Perceptually uniform map from dark blue to yellow" },
+    Colormap { name: "MAGMA"   , function: &MAGMA   , cyclic: false, description: "Perceptually uniform map from black through purple to light yellow" },
+    Colormap { name: "INFERNO" , function: &INFERNO , cyclic: false, description: "Perceptually uniform map from black through red to light yellow" },
+    Colormap { name: "TWILIGHT", function: &TWILIGHT, cyclic: true , description: "Cyclic map from white through blue & black to red, an approximation of twilight" },
+];
+
+/// Function for getting a colormap from its name
+pub fn get_colormap(colormap: &str) -> Result<&'static Colormap, KyrosError> {
+
+    // Tries to find colormap in COLORMAPS const
+    for value in COLORMAPS.iter() {
+        if value.name == colormap {
+            return Ok(value);
+        }
+    }
+
+    // If not found return error
+    return Err(KyrosError::not_found(
+        "Colormap",
+        "Colormaps",
+        colormap,
+        COLORMAPS.iter().map(|v| (v.name, v.description)),
+    ));
+}
+
+/// Values of the escaped pixels of a whole image, sorted, for the normalizations that stretch them over the map
+#[derive(Debug, Clone, Default)]
+pub struct ValueRange {
+    sorted: Vec<f64>,
+}
+
+impl ValueRange {
+    /// Collects the values to normalize by, non-finite values are left out
+    pub fn new(values: impl Iterator<Item = f64>) -> ValueRange {
+        let mut sorted: Vec<f64> = values.filter(|v| v.is_finite()).collect();
+        sorted.sort_by(|a, b| a.total_cmp(b));
+        return ValueRange { sorted };
+    }
+
+    fn min(&self) -> f64 {
+        return self.sorted.first().copied().unwrap_or(0.0);
+    }
+
+    fn max(&self) -> f64 {
+        return self.sorted.last().copied().unwrap_or(0.0);
+    }
+
+    /// Smallest value above 0, the start of the LOG normalization
+    fn min_positive(&self) -> f64 {
+        let index = self.sorted.partition_point(|v| *v <= 0.0);
+        return self.sorted.get(index).copied().unwrap_or(0.0);
+    }
+}
+
+fn CYCLE(value: f64, _: &ValueRange) -> f64 {
+    return value / HUE_CYCLE;
+}
+
+fn LINEAR(value: f64, range: &ValueRange) -> f64 {
+    let width = range.max() - range.min();
+    if width <= 0.0 {
+        return 0.0;
+    }
+    return (value - range.min()) / width;
+}
+
+/// Log of the value between the smallest positive value & the largest, so scaling every value doesn't change it.
+/// Values up to the smallest positive one all go to the start of the map.
+fn LOG(value: f64, range: &ValueRange) -> f64 {
+    let (low, high) = (range.min_positive(), range.max());
+    if low <= 0.0 || high <= low {
+        return 0.0;
+    }
+    return (value.max(low) / low).ln() / (high / low).ln();
+}
+
+/// Rank of the value in the cumulative histogram of the image (ties share the middle of their ranks)
+fn HISTOGRAM(value: f64, range: &ValueRange) -> f64 {
+    if range.sorted.is_empty() {
+        return 0.0;
+    }
+    let below = range.sorted.partition_point(|v| *v < value);
+    let up_to = range.sorted.partition_point(|v| *v <= value);
+    return (below + up_to) as f64 / (2 * range.sorted.len()) as f64;
+}
+
+/// Type of every normalization, `(value, values of the image) -> position along the colormap (in turns of the color function)`
+pub type NormalizeFn = dyn Fn(f64, &ValueRange) -> f64 + Sync;
+
+/// Entry of the NORMALIZATIONS table
+pub struct Normalization {
+    pub name: &'static str,
+    pub function: &'static NormalizeFn,
+    pub whole_image: bool, // Needs the values of the whole image, rather than only the value of the pixel
+    pub description: &'static str,
+}
+
+/// Sets Bootleg hashmap for normalizations
+pub const NORMALIZATIONS: [Normalization;4] = [
+    Normalization { name: "CYCLE"    , function: &CYCLE    , whole_image: false, description: "Repeats the map every 40 units of the value (one turn of ROTATIONAL)" },
+    Normalization { name: "LINEAR"   , function: &LINEAR   , whole_image: true , description: "Stretches the values of the image linearly over the map (or one turn of the hue)" },
+    Normalization { name: "LOG"      , function: &LOG      , whole_image: true , description: "\tStretches the log of the positive values of the image over the map" },
+    Normalization { name: "HISTOGRAM", function: &HISTOGRAM, whole_image: true , description: "Spreads the values of the image evenly by their rank (histogram equalization)" },
+];
+
+/// Function for getting a normalization from its name, CYCLE when none is set
+pub fn get_normalization(normalization: Option<&str>) -> Result<&'static Normalization, KyrosError> {
+    let normalization = normalization.unwrap_or("CYCLE");
+
+    // Tries to find normalization in NORMALIZATIONS const
+    for value in NORMALIZATIONS.iter() {
+        if value.name == normalization {
+            return Ok(value);
+        }
+    }
+
+    // If not found return error
+    return Err(KyrosError::not_found(
+        "Normalization",
+        "Normalizations",
+        normalization,
+        NORMALIZATIONS.iter().map(|v| (v.name, v.description)),
+    ));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn log_is_scale_invariant() {
+        let values = [1e-9, 1e-8, 1e-7, 1e-5, 1e-3];
+        let range = ValueRange::new(values.iter().copied());
+        let scaled = ValueRange::new(values.iter().map(|v| v * 1e6));
+
+        for value in values {
+            let position = LOG(value, &range);
+            assert!((position - LOG(value * 1e6, &scaled)).abs() < 1e-12);
+        }
+        assert_eq!(LOG(1e-9, &range), 0.0);
+        assert!((LOG(1e-7, &range) - 1.0 / 3.0).abs() < 1e-12);
+        assert_eq!(LOG(1e-3, &range), 1.0);
+    }
+
+    #[test]
+    fn log_starts_at_the_smallest_positive_value() {
+        let range = ValueRange::new([0.0, -2.0, 1.0, 10.0, 100.0].into_iter());
+        assert_eq!(LOG(-2.0, &range), 0.0);
+        assert_eq!(LOG(0.0, &range), 0.0);
+        assert!((LOG(10.0, &range) - 0.5).abs() < 1e-12);
+        assert_eq!(LOG(5.0, &ValueRange::new([0.0, 0.0].into_iter())), 0.0);
+    }
+
+    #[test]
+    fn histogram_spreads_values_by_rank() {
+        let range = ValueRange::new([1.0, 1000.0, 2.0, 3.0, f64::NAN].into_iter());
+
+        // Evenly spaced ranks, whatever the gaps between the values
+        assert_eq!(HISTOGRAM(1.0, &range), 0.125);
+        assert_eq!(HISTOGRAM(2.0, &range), 0.375);
+        assert_eq!(HISTOGRAM(3.0, &range), 0.625);
+        assert_eq!(HISTOGRAM(1000.0, &range), 0.875);
+        assert_eq!(HISTOGRAM(0.0, &range), 0.0);
+        assert_eq!(HISTOGRAM(2000.0, &range), 1.0);
+
+        // Ties share the middle of their ranks
+        let ties = ValueRange::new([5.0, 5.0, 5.0, 5.0].into_iter());
+        assert_eq!(HISTOGRAM(5.0, &ties), 0.5);
+        assert_eq!(HISTOGRAM(5.0, &ValueRange::default()), 0.0);
+    }
+
+    #[test]
+    fn linear_and_cycle() {
+        let range = ValueRange::new([2.0, 4.0, 10.0].into_iter());
+        assert_eq!(LINEAR(2.0, &range), 0.0);
+        assert_eq!(LINEAR(6.0, &range), 0.5);
+        assert_eq!(LINEAR(10.0, &range), 1.0);
+        assert_eq!(LINEAR(3.0, &ValueRange::new([3.0].into_iter())), 0.0);
+
+        assert_eq!(CYCLE(HUE_CYCLE, &range), 1.0);
+        assert_eq!(CYCLE(HUE_CYCLE / 4.0, &ValueRange::default()), 0.25);
+    }
+
+    #[test]
+    fn only_cycle_works_in_bands() {
+        for normalization in NORMALIZATIONS.iter() {
+            assert_eq!(normalization.whole_image, normalization.name != "CYCLE");
+        }
+        assert_eq!(get_normalization(None).unwrap().name, "CYCLE");
+        assert!(get_normalization(Some("HISTOGRAMS")).is_err());
+    }
+}
@@ -1,2 +1,4 @@
 pub mod color;
 pub mod shadows;
+pub mod palette;
+pub mod colormap;
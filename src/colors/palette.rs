@@ -0,0 +1,447 @@
+/*
+  File for gradient palettes, used in place of the hue wheel. The hue of the color function
+  picks a position along the gradient, which is blended between its stops in OKLab so the
+  steps between colors look even. Three formats are read:
+
+    Kyros palettes (any other extension), a stop on every line as its position & color,
+    positions can be left out to space the stops evenly:
+        # Ocean
+        0.0  #000764
+        0.4  #206BCB
+        0.7  #EDFFFF
+        1.0  #FFAA00
+
+    GIMP gradients (.ggr), the segments are blended through their middle points with their
+    blend function (linear, curved, sine, sphere or step) in RGB or around the hue in HSV.
+
+    Fractint maps (.map), a color as "R G B" (0 to 255) on every line, spaced evenly.
+*/
+
+use crate::error::KyrosError;
+use crate::structs::Config;
+
+use std::fs;
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+/// Gradient of colors between stops, with how it is laid along the hue of the color function
+#[derive(Debug, Clone)]
+pub struct Palette {
+    stops: Vec<(f64, [f64;3])>, // Position in [0, 1] & color in OKLab of every stop, sorted by position
+    pub offset: f64,            // Fraction of the palette it is moved along by
+    pub scale: f64,             // Amount of times the palette repeats over one turn of the hue
+}
+
+impl Palette {
+    /// Sets up a palette from its stops, as positions in [0, 1] & sRGB colors in [0, 1]
+    pub fn new(mut stops: Vec<(f64, [f64;3])>) -> Palette {
+        stops.sort_by(|a, b| a.0.total_cmp(&b.0));
+        return Palette {
+            stops: stops.into_iter().map(|(position, rgb)| (position, srgb_to_oklab(rgb))).collect(),
+            offset: 0.0,
+            scale: 1.0,
+        };
+    }
+
+    /// Gets the color of a hue (in turns) at a brightness in [0, 1]
+    pub fn color(&self, turns: f64, brightness: f64) -> (u8, u8, u8) {
+        let position = (turns * self.scale + self.offset).rem_euclid(1.0);
+
+        // Past the first or last stop the palette keeps their color
+        let next = self.stops.iter().position(|stop| stop.0 > position).unwrap_or(self.stops.len());
+        let lab = match next {
+            0 => self.stops[0].1,
+            next if next == self.stops.len() => self.stops[next - 1].1,
+            next => {
+                let (start, end) = (&self.stops[next - 1], &self.stops[next]);
+                let t = (position - start.0) / (end.0 - start.0);
+                [0, 1, 2].map(|i| start.1[i] + (end.1[i] - start.1[i]) * t)
+            },
+        };
+
+        let rgb = oklab_to_srgb(lab).map(|v| (255.0 * (v * brightness).clamp(0.0, 1.0)).round() as u8);
+        return (rgb[0], rgb[1], rgb[2]);
+    }
+}
+
+/// Function for getting the palette set in config (if any), read from its file.
+/// Palettes are kept, so the bands & frames of a render only read their file once (or again once it changes).
+pub fn get_palette(config: &Config) -> Result<Option<Palette>, KyrosError> {
+    static PALETTES: Mutex<Vec<(String, Option<SystemTime>, Palette)>> = Mutex::new(Vec::new());
+
+    let Some(path) = &config.palette else {
+        return Ok(None);
+    };
+    let modified = fs::metadata(path).and_then(|metadata| metadata.modified()).ok();
+
+    // A render that panicked while holding the lock can't have left the list half changed
+    let mut palettes = PALETTES.lock().unwrap_or_else(|error| error.into_inner());
+    let mut palette = match palettes.iter().find(|(read_path, read_modified, _)| read_path == path && *read_modified == modified) {
+        Some((_, _, palette)) => palette.clone(),
+        None => {
+            let palette = read_palette(path)?;
+            palettes.retain(|(read_path, _, _)| read_path != path);
+            palettes.push((path.clone(), modified, palette.clone()));
+            palette
+        },
+    };
+    palette.offset = config.palette_offset;
+    palette.scale = config.palette_scale.unwrap_or(1.0);
+    return Ok(Some(palette));
+}
+
+/// Function for reading a palette file, the format is picked by its extension
+pub fn read_palette(path: &str) -> Result<Palette, KyrosError> {
+    let text = fs::read_to_string(path).map_err(|error| KyrosError::io(path, error))?;
+    let invalid = |message: String| KyrosError::InvalidFile { path: path.to_string(), message };
+
+    let lowercase = path.to_lowercase();
+    let stops = match () {
+        _ if lowercase.ends_with(".ggr") => parse_ggr(&text),
+        _ if lowercase.ends_with(".map") => parse_map(&text),
+        _ => parse_kyros(&text),
+    }.map_err(invalid)?;
+
+    if stops.is_empty() {
+        return Err(invalid("it has no colors".to_string()));
+    }
+    return Ok(Palette::new(stops));
+}
+
+/// Spaces colors evenly over [0, 1]
+fn spread(colors: Vec<[f64;3]>) -> Vec<(f64, [f64;3])> {
+    let last = (colors.len().max(2) - 1) as f64;
+    return colors.into_iter().enumerate().map(|(i, color)| (i as f64 / last, color)).collect();
+}
+
+/// Parses a Kyros palette, lines of "[POSITION] #RRGGBB"
+fn parse_kyros(text: &str) -> Result<Vec<(f64, [f64;3])>, String> {
+    let mut positions = Vec::new();
+    let mut colors = Vec::new();
+
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with("//") || line.starts_with(';') || (line.starts_with('#') && !is_hex_color(line)) {
+            continue;
+        }
+        let parts: Vec<&str> = line.split_whitespace().collect();
+        let error = || format!("line {} must be written as [POSITION] #RRGGBB", number + 1);
+
+        let (position, color) = match parts.as_slice() {
+            [color, ..] if parse_hex(color).is_some() => (None, *color),
+            [position, color, ..] => (Some(position.parse::<f64>().map_err(|_| error())?), *color),
+            _ => return Err(error()),
+        };
+        positions.push(position);
+        colors.push(parse_hex(color).ok_or_else(error)?);
+    }
+
+    return match positions.iter().all(|v| v.is_some()) {
+        true => Ok(positions.into_iter().flatten().map(|v| v.clamp(0.0, 1.0)).zip(colors).collect()),
+        false if positions.iter().all(|v| v.is_none()) => Ok(spread(colors)),
+        false => Err("every stop needs a position when any of them has one".to_string()),
+    };
+}
+
+/// Checks if a line starts with a color such as #FFAA00, rather than a comment
+fn is_hex_color(line: &str) -> bool {
+    return parse_hex(line.split_whitespace().next().unwrap_or("")).is_some();
+}
+
+/// Parses a color written as #RRGGBB
+pub(crate) fn parse_hex(text: &str) -> Option<[f64;3]> {
+    let hex = text.strip_prefix('#')?;
+    if hex.len() != 6 || !hex.chars().all(|v| v.is_ascii_hexdigit()) {
+        return None;
+    }
+    let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).ok().map(|v| v as f64 / 255.0);
+    return Some([channel(0)?, channel(2)?, channel(4)?]);
+}
+
+/// Amount of stops a segment of a GIMP gradient is sampled into, when it isn't blended linearly in RGB
+const GGR_SEGMENT_SAMPLES: usize = 16;
+
+/// Parses a GIMP gradient. Segments blended linearly in RGB give a stop at both ends & one at
+/// their middle point, the rest (curved, sine, sphere & step blends, or HSV colors) are sampled.
+fn parse_ggr(text: &str) -> Result<Vec<(f64, [f64;3])>, String> {
+    let mut lines = text.lines().map(|line| line.trim()).filter(|line| !line.is_empty());
+    if lines.next() != Some("GIMP Gradient") {
+        return Err("it doesn't start with 'GIMP Gradient'".to_string());
+    }
+
+    let mut line = lines.next().ok_or("it has no segments")?;
+    if line.starts_with("Name:") {
+        line = lines.next().ok_or("it has no segments")?;
+    }
+    let segments: usize = line.parse().map_err(|_| format!("'{}' isn't an amount of segments", line))?;
+
+    let mut stops = Vec::new();
+    for index in 0..segments {
+        let line = lines.next().ok_or_else(|| format!("it has {} of its {} segments", index, segments))?;
+        let values: Vec<f64> = line
+            .split_whitespace()
+            .take(13)
+            .map(|v| v.parse::<f64>())
+            .collect::<Result<_, _>>()
+            .map_err(|_| format!("segment {} isn't a list of numbers", index + 1))?;
+        if values.len() < 11 {
+            return Err(format!("segment {} needs at least 11 numbers", index + 1));
+        }
+
+        let (left, middle, right) = (values[0], values[1], values[2]);
+        let left_color = [values[3], values[4], values[5]];
+        let right_color = [values[7], values[8], values[9]];
+
+        // Older gradients leave out the blend function & color type, which are linear & RGB then
+        let blend = values.get(11).copied().unwrap_or(0.0);
+        let coloring = values.get(12).copied().unwrap_or(0.0);
+        if !matches!(blend as u8, 0..=5) || blend.fract() != 0.0 {
+            return Err(format!("segment {} has an unknown blend function {}", index + 1, blend));
+        }
+        if !matches!(coloring as u8, 0..=2) || coloring.fract() != 0.0 {
+            return Err(format!("segment {} has an unknown color type {}", index + 1, coloring));
+        }
+
+        if blend == 0.0 && coloring == 0.0 {
+            let middle_color = [0, 1, 2].map(|i| 0.5 * (left_color[i] + right_color[i]));
+
+            stops.push((left, left_color));
+            if middle > left && middle < right {
+                stops.push((middle, middle_color));
+            }
+            stops.push((right, right_color));
+            continue;
+        }
+
+        // Step blends jump from one color to the other at the middle point
+        if blend == 5.0 {
+            stops.extend([(left, left_color), (middle, left_color), (middle, right_color), (right, right_color)]);
+            continue;
+        }
+
+        let width = right - left;
+        let middle = match width > 0.0 {
+            true => ((middle - left) / width).clamp(0.0, 1.0),
+            false => 0.5,
+        };
+        for sample in 0..=GGR_SEGMENT_SAMPLES {
+            let t = sample as f64 / GGR_SEGMENT_SAMPLES as f64;
+            let factor = ggr_blend(blend as u8, middle, t);
+            stops.push((left + width * t, ggr_color(coloring as u8, left_color, right_color, factor)));
+        }
+    }
+    return Ok(stops);
+}
+
+/// Gets how far along a segment of a GIMP gradient the color is at `t` (both in [0, 1]), the way GIMP does.
+/// Blend functions are 0 linear, 1 curved, 2 sine, 3 sphere increasing & 4 sphere decreasing.
+fn ggr_blend(blend: u8, middle: f64, t: f64) -> f64 {
+    let linear = match () {
+        _ if t <= middle && middle < 1e-10 => 0.0,
+        _ if t <= middle => 0.5 * t / middle,
+        _ if 1.0 - middle < 1e-10 => 1.0,
+        _ => 0.5 + 0.5 * (t - middle) / (1.0 - middle),
+    };
+    return match blend {
+        1 => t.powf(0.5f64.ln() / middle.max(1e-10).ln()),
+        2 => 0.5 * ((std::f64::consts::PI * (linear - 0.5)).sin() + 1.0),
+        3 => (1.0 - (linear - 1.0).powi(2)).sqrt(),
+        4 => 1.0 - (1.0 - linear.powi(2)).sqrt(),
+        _ => linear,
+    };
+}
+
+/// Blends the two colors of a segment of a GIMP gradient in RGB, or in HSV going around the hue
+/// counter-clockwise (color type 1) or clockwise (color type 2)
+fn ggr_color(coloring: u8, left: [f64;3], right: [f64;3], factor: f64) -> [f64;3] {
+    if coloring == 0 {
+        return [0, 1, 2].map(|i| left[i] + (right[i] - left[i]) * factor);
+    }
+
+    let (left, right) = (rgb_to_hsv(left), rgb_to_hsv(right));
+    let turn = match (coloring, left[0] < right[0]) {
+        (1, true) => right[0] - left[0],
+        (1, false) => 1.0 - (left[0] - right[0]),
+        (_, true) => -(1.0 - (right[0] - left[0])),
+        (_, false) => -(left[0] - right[0]),
+    };
+    let hue = (left[0] + turn * factor).rem_euclid(1.0);
+    return hsv_to_rgb([hue, left[1] + (right[1] - left[1]) * factor, left[2] + (right[2] - left[2]) * factor]);
+}
+
+/// Turns an RGB color into hue (in turns), saturation & value, all in [0, 1]
+fn rgb_to_hsv(rgb: [f64;3]) -> [f64;3] {
+    let max = rgb[0].max(rgb[1]).max(rgb[2]);
+    let min = rgb[0].min(rgb[1]).min(rgb[2]);
+    let delta = max - min;
+    let hue = match () {
+        _ if delta <= 0.0 => 0.0,
+        _ if max == rgb[0] => ((rgb[1] - rgb[2]) / delta).rem_euclid(6.0),
+        _ if max == rgb[1] => (rgb[2] - rgb[0]) / delta + 2.0,
+        _ => (rgb[0] - rgb[1]) / delta + 4.0,
+    };
+    let saturation = if max > 0.0 { delta / max } else { 0.0 };
+    return [hue / 6.0, saturation, max];
+}
+
+/// Turns hue (in turns), saturation & value into an RGB color
+fn hsv_to_rgb(hsv: [f64;3]) -> [f64;3] {
+    let [hue, saturation, value] = hsv;
+    let channel = |n: f64| {
+        let k = (n + hue * 6.0).rem_euclid(6.0);
+        value - value * saturation * k.min(4.0 - k).clamp(0.0, 1.0)
+    };
+    return [channel(5.0), channel(3.0), channel(1.0)];
+}
+
+/// Parses a Fractint map, lines of "R G B" from 0 to 255 (anything after them is a comment)
+fn parse_map(text: &str) -> Result<Vec<(f64, [f64;3])>, String> {
+    let mut colors = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let values: Vec<&str> = line.split_whitespace().take(3).collect();
+        if values.is_empty() {
+            continue;
+        }
+        let error = || format!("line {} must start with 'R G B' from 0 to 255", number + 1);
+        if values.len() < 3 {
+            return Err(error());
+        }
+        let mut color = [0.0;3];
+        for (channel, value) in color.iter_mut().zip(values.iter()) {
+            *channel = value.parse::<u8>().map_err(|_| error())? as f64 / 255.0;
+        }
+        colors.push(color);
+    }
+    return Ok(spread(colors));
+}
+
+/// Turns an sRGB channel into linear light
+fn to_linear(v: f64) -> f64 {
+    return match v <= 0.04045 {
+        true => v / 12.92,
+        false => ((v + 0.055) / 1.055).powf(2.4),
+    };
+}
+
+/// Turns linear light into an sRGB channel
+fn from_linear(v: f64) -> f64 {
+    return match v <= 0.0031308 {
+        true => v * 12.92,
+        false => 1.055 * v.powf(1.0 / 2.4) - 0.055,
+    };
+}
+
+/// Converts an sRGB color into OKLab (L, a, b)
+pub(crate) fn srgb_to_oklab(rgb: [f64;3]) -> [f64;3] {
+    let [r, g, b] = rgb.map(to_linear);
+    let l = (0.4122214708 * r + 0.5363325363 * g + 0.0514459929 * b).cbrt();
+    let m = (0.2119034982 * r + 0.6806995451 * g + 0.1073969566 * b).cbrt();
+    let s = (0.0883024619 * r + 0.2817188376 * g + 0.6299787005 * b).cbrt();
+    return [
+        0.2104542553 * l + 0.7936177850 * m - 0.0040720468 * s,
+        1.9779984951 * l - 2.4285922050 * m + 0.4505937099 * s,
+        0.0259040371 * l + 0.7827717662 * m - 0.8086757660 * s,
+    ];
+}
+
+/// Converts an OKLab color (L, a, b) into sRGB
+pub(crate) fn oklab_to_srgb(lab: [f64;3]) -> [f64;3] {
+    let [lightness, a, b] = lab;
+    let l = (lightness + 0.3963377774 * a + 0.2158037573 * b).powi(3);
+    let m = (lightness - 0.1055613458 * a - 0.0638541728 * b).powi(3);
+    let s = (lightness - 0.0894841775 * a - 1.2914855480 * b).powi(3);
+    return [
+        4.0767416621 * l - 3.3077115913 * m + 0.2309699292 * s,
+        -1.2684380046 * l + 2.6097574011 * m - 0.3413193965 * s,
+        -0.0041960863 * l - 0.7034186147 * m + 1.7076147010 * s,
+    ].map(|v| from_linear(v.clamp(0.0, 1.0)));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::temp_path;
+
+    use std::fs::File;
+    use std::time::Duration;
+
+    fn ggr(segment: &str) -> Result<Vec<(f64, [f64;3])>, String> {
+        return parse_ggr(&format!("GIMP Gradient\nName: Test\n1\n{}\n", segment));
+    }
+
+    fn close(a: [f64;3], b: [f64;3]) -> bool {
+        return (0..3).all(|i| (a[i] - b[i]).abs() < 1e-9);
+    }
+
+    #[test]
+    fn linear_rgb_segments_give_three_stops() {
+        let stops = ggr("0 0.5 1 0 0 0 1 1 1 1 1 0 0").unwrap();
+        assert_eq!(stops.len(), 3);
+        assert!(close(stops[1].1, [0.5;3]));
+
+        // Older gradients without a blend function & color type are linear RGB
+        assert_eq!(ggr("0 0.5 1 0 0 0 1 1 1 1 1").unwrap().len(), 3);
+    }
+
+    #[test]
+    fn blend_functions_follow_gimp() {
+        // Curved puts half of the way at the middle point, like linear
+        let stops = ggr("0 0.25 1 0 0 0 1 1 1 1 1 1 0").unwrap();
+        let quarter = stops.iter().find(|stop| (stop.0 - 0.25).abs() < 1e-12).unwrap();
+        assert!(close(quarter.1, [0.5;3]));
+
+        // Sphere increasing rises faster than linear, sphere decreasing slower
+        let increasing = ggr("0 0.5 1 0 0 0 1 1 1 1 1 3 0").unwrap();
+        let decreasing = ggr("0 0.5 1 0 0 0 1 1 1 1 1 4 0").unwrap();
+        assert!(increasing[4].1[0] > 0.25 && decreasing[4].1[0] < 0.25);
+
+        // Step jumps at the middle point
+        let stops = ggr("0 0.3 1 0 0 0 1 1 1 1 1 5 0").unwrap();
+        assert_eq!(stops.iter().map(|stop| stop.0).collect::<Vec<_>>(), vec![0.0, 0.3, 0.3, 1.0]);
+    }
+
+    #[test]
+    fn hsv_segments_go_around_the_hue() {
+        // Red to blue passes green counter-clockwise & magenta clockwise
+        let counter_clockwise = ggr("0 0.5 1 1 0 0 1 0 0 1 1 0 1").unwrap();
+        let clockwise = ggr("0 0.5 1 1 0 0 1 0 0 1 1 0 2").unwrap();
+        assert!(close(counter_clockwise[GGR_SEGMENT_SAMPLES / 2].1, [0.0, 1.0, 0.0]));
+        assert!(close(clockwise[GGR_SEGMENT_SAMPLES / 2].1, [1.0, 0.0, 1.0]));
+    }
+
+    #[test]
+    fn hex_colors() {
+        assert_eq!(parse_hex("#FF0080"), Some([1.0, 0.0, 128.0 / 255.0]));
+        assert_eq!(parse_hex("#ff0080"), parse_hex("#FF0080"));
+        assert_eq!(parse_hex("FF0080"), None);
+        assert_eq!(parse_hex("#FF008"), None);
+        assert_eq!(parse_hex("#FF00800"), None);
+        assert_eq!(parse_hex("#+1+1+1"), None);
+        assert_eq!(parse_hex("#GG0080"), None);
+    }
+
+    #[test]
+    fn unknown_segment_types_are_errors() {
+        assert!(ggr("0 0.5 1 0 0 0 1 1 1 1 1 6 0").is_err());
+        assert!(ggr("0 0.5 1 0 0 0 1 1 1 1 1 0 3").is_err());
+    }
+    #[test]
+    fn palettes_are_read_once() {
+        let path = temp_path("cached.map");
+        let config = Config { palette: Some(path.clone()), palette_offset: 0.5, ..Config::default() };
+        let set_modified = |seconds| File::options().write(true).open(&path).unwrap().set_modified(SystemTime::UNIX_EPOCH + Duration::from_secs(seconds)).unwrap();
+
+        fs::write(&path, "255 0 0\n255 0 0\n").unwrap();
+        set_modified(1000);
+        let red = get_palette(&config).unwrap().unwrap();
+        assert_eq!((red.color(0.0, 1.0), red.offset), ((255, 0, 0), 0.5));
+
+        // The same file is only read again once it changes
+        fs::write(&path, "0 0 255\n0 0 255\n").unwrap();
+        set_modified(1000);
+        assert_eq!(get_palette(&config).unwrap().unwrap().color(0.0, 1.0), (255, 0, 0));
+        set_modified(2000);
+        assert_eq!(get_palette(&config).unwrap().unwrap().color(0.0, 1.0), (0, 0, 255));
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,9 +1,7 @@
 #![allow(non_snake_case)]
 
-use super::super::Args;
-
-use clap::error::ErrorKind;
-use clap::CommandFactory;
+use crate::error::KyrosError;
+use crate::math::measurement::{distance_in_pixels, Sample};
 
 /*
     Author : Mark T
@@ -12,46 +10,62 @@ use clap::CommandFactory;
 
 
 /// Rotational Coloring function for generation. Uses HSV rotational color. 
-fn NONE(_: f64) -> f64 {
+fn NONE(_: &Sample) -> f64 {
     // Gets color value
     return 1.0;
 }
 
-fn MINIMAL(n: f64) -> f64{
+fn MINIMAL(sample: &Sample) -> f64{
+    let n = sample.value;
     return 0.125 * (n * 9.0).cos() + 0.815;
 }
 
-fn MODULUS(n: f64) -> f64 {
+fn MODULUS(sample: &Sample) -> f64 {
+    let n = sample.value;
     let modulus_value = 3.0;
 
     return 1.0 - (n.rem_euclid(modulus_value) / modulus_value);
 }
 
-const SHADOWS: [(&str, &dyn Fn(f64) -> f64, &str);3] = [
+/// Darkens orbits that took longer to finish, such as slowly converging ones
+fn SPEED(sample: &Sample) -> f64 {
+    return 0.25 + 0.75 * (-(sample.steps as f64) / 24.0).exp();
+}
+
+/// Width in pixels of the lines BOUNDARY draws, the same at any zoom
+const BOUNDARY_WIDTH: f64 = 1.5;
+
+/// Darkens pixels close to the boundary of the set into lines, for the DISTANCE measurement
+fn BOUNDARY(sample: &Sample) -> f64 {
+    return (distance_in_pixels(sample.value) / BOUNDARY_WIDTH).clamp(0.0, 1.0);
+}
+
+/// Type of every shadow function
+pub type ShadowFn = dyn Fn(&Sample) -> f64 + Sync;
+
+pub const SHADOWS: [(&str, &ShadowFn, &str);5] = [
     ("NONE"    , &NONE    , "\tDoesn't change values, sets all lightness values to '1'"),
     ("MINIMAL" , &MINIMAL, "Adds slight variance to values based on cos wave"),
     ("MODULUS" , &MODULUS , "Adds significant variance using a sawtooth wave"),
+    ("SPEED"   , &SPEED   , "\tDarkens pixels by the amount of iterations, (for convergent formulas)"),
+    ("BOUNDARY", &BOUNDARY, "Draws lines along the boundary of the set, (for the DISTANCE measurement)"),
 ];
 
 /// Function for getting the shadow formula from config
-pub fn get_shadow(shadow: &str) -> &dyn Fn(f64) -> f64 {
+pub fn get_shadow(shadow: &str) -> Result<&'static ShadowFn, KyrosError> {
 
     // Tries to find function in FORMULAS const
     for (key, value, _) in SHADOWS.iter() {
         if key == &shadow {
-            return value;
+            return Ok(value);
         }
     }
 
-    let shadow_string: String = SHADOWS
-        .iter()
-        .map(|v| format!("  {}\t{}", v.0, v.2))
-        .collect::<Vec<String>>()
-        .join("\n");
-
-    // If not found throw error
-    Args::command().error(
-        ErrorKind::InvalidValue,
-        format!("Shadow generation method '{}' not found!\n\nAllowed Shadows:\n{}", shadow, shadow_string)
-    ).exit();
+    // If not found return error
+    return Err(KyrosError::not_found(
+        "Shadow generation method",
+        "Shadows",
+        shadow,
+        SHADOWS.iter().map(|v| (v.0, v.2)),
+    ));
 }
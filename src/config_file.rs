@@ -0,0 +1,212 @@
+/*
+  File for reading configs from TOML / JSON files & writing them back out.
+  Files only need the fields they change, such as:
+
+    max_i = 4096
+    gen_formula = "BS"
+    c_init = { real = -0.8, imaginary = 0.156 }
+
+    [math_frame]
+    center_re = -1.75
+    zoom = 30.0
+*/
+
+use crate::error::KyrosError;
+use crate::structs::Config;
+
+use serde_json::Value;
+
+use std::fs;
+
+/// Function for reading a config file (JSON when the name ends in .json, TOML otherwise).
+/// Every field the file doesn't set keeps its value from `base`.
+pub fn read_config_file(path: &str, base: &Config) -> Result<Config, KyrosError> {
+    return apply_table(path, base, read_table(path)?);
+}
+
+/// Reads a TOML (or .json) file into a table of values
+pub(crate) fn read_table(path: &str) -> Result<Value, KyrosError> {
+    let text = fs::read_to_string(path).map_err(|error| KyrosError::io(path, error))?;
+    let invalid = |message: String| KyrosError::InvalidFile { path: path.to_string(), message };
+
+    return match path.to_lowercase().ends_with(".json") {
+        true => serde_json::from_str(&text).map_err(|error| invalid(format!("it isn't valid JSON, {}", error))),
+        false => toml::from_str(&text).map_err(|error| invalid(format!("it isn't valid TOML, {}", error.to_string().trim_end()))),
+    };
+}
+
+/// Fields a config file can set, with what they set
+pub const CONFIG_FIELDS: [(&str, &str);28] = [
+    ("count"         , "\tIndex of the generated image"),
+    ("c_init"        , "Constant of julia sets, as { real, imaginary }"),
+    ("size_x"        , "Width of the image"),
+    ("size_y"        , "Height of the image"),
+    ("max_i"         , "\tMaximum iterations of every pixel"),
+    ("gen_formula"   , "Formula of the generator"),
+    ("formula_expr"  , "Formula written as an expression, used in place of gen_formula"),
+    ("polynomial"    , "Coefficients (highest power first) of the polynomial of Newton style formulas"),
+    ("color_formula" , "Color function (or colormap)"),
+    ("hue_offset"    , "Degrees added to the hue of the color function"),
+    ("palette"       , "Gradient file used in place of the hue wheel"),
+    ("palette_offset", "Fraction of the palette it is moved along by"),
+    ("palette_scale" , "Amount of times the palette repeats over the hue wheel"),
+    ("normalization" , "Way values are put along the colormap or hue wheel"),
+    ("shadow_formula", "Shadow function"),
+    ("measurement"   , "Measurement that turns each orbit into a value"),
+    ("bailout"       , "Escape radius"),
+    ("escape_norm"   , "Escape test"),
+    ("interior"      , "Way orbits that never escape are colored"),
+    ("instant_escape", "Color of pixels that escape before the first step"),
+    ("save_method"   , "Way the image is saved"),
+    ("output"        , "Name of the saved file without its extension"),
+    ("math_frame"    , "Viewport of the image, as a table"),
+    ("progress"      , "Shows the progress of the render"),
+    ("threads"       , "Amount of worker threads to render with"),
+    ("band_rows"     , "Amount of rows rendered at a time by streaming save methods"),
+    ("fps"           , "\tFrames per second of animated save methods"),
+    ("deep_zoom"     , "Forces the perturbation renderer even for shallow zooms"),
+];
+
+/// Fields the math_frame table of a config file can set, with what they set
+pub const MATH_FRAME_FIELDS: [(&str, &str);5] = [
+    ("center_re"   , "Real value at the center of the image"),
+    ("center_im"   , "Imaginary value at the center of the image"),
+    ("center_exact", "Center as written, [re, im], for deep zooms past f64 precision"),
+    ("zoom"        , "\tMagnification, 1.0 fits [-2, 2] on the shorter side of the image"),
+    ("rotation"    , "Rotation of the viewport around its center in degrees"),
+];
+
+/// Checks every key of `table` is one of `fields`, so misspelled fields aren't left out without a word
+pub(crate) fn check_fields(
+    table: &Value,
+    kind: &'static str,
+    group: &'static str,
+    fields: &[(&'static str, &'static str)],
+) -> Result<(), KyrosError> {
+    let Value::Object(table) = table else {
+        return Ok(());
+    };
+    for key in table.keys() {
+        if !fields.iter().any(|field| field.0 == key) {
+            return Err(KyrosError::not_found(kind, group, key, fields.iter().copied()));
+        }
+    }
+    return Ok(());
+}
+
+/// Sets every field of `base` that the table read from `path` has
+pub(crate) fn apply_table(path: &str, base: &Config, table: Value) -> Result<Config, KyrosError> {
+    let invalid = |message: String| KyrosError::InvalidFile { path: path.to_string(), message };
+
+    check_fields(&table, "Config field", "Config fields", &CONFIG_FIELDS)?;
+    if let Some(math_frame) = table.get("math_frame") {
+        check_fields(math_frame, "Math frame field", "Math frame fields", &MATH_FRAME_FIELDS)?;
+    }
+
+    let sets_re = table.pointer("/math_frame/center_re").is_some();
+    let sets_im = table.pointer("/math_frame/center_im").is_some();
+    let sets_center = sets_re || sets_im;
+    let sets_exact = table.pointer("/math_frame/center_exact").is_some();
+
+    let mut config = serde_json::to_value(base).map_err(|error| invalid(error.to_string()))?;
+    merge(&mut config, table);
+    let mut config: Config = serde_json::from_value(config).map_err(|error| invalid(format!("{}", error)))?;
+
+    // A center given without its exact digits replaces the exact digits of the parts it sets
+    if sets_center && !sets_exact {
+        let frame = &mut config.math_frame;
+        let (mut exact_re, mut exact_im) = base.math_frame.center_exact
+            .clone()
+            .unwrap_or((base.math_frame.center_re.to_string(), base.math_frame.center_im.to_string()));
+        if sets_re {
+            exact_re = frame.center_re.to_string();
+        }
+        if sets_im {
+            exact_im = frame.center_im.to_string();
+        }
+        frame.center_exact = Some((exact_re, exact_im));
+    }
+
+    // Exact digits given without a center set the center too
+    if sets_exact && !sets_center {
+        if let Some((real, imaginary)) = &config.math_frame.center_exact {
+            let parse = |value: &str| value.trim().parse::<f64>().map_err(|_| invalid(format!("center '{}' is not a number", value)));
+            config.math_frame.center_re = parse(real)?;
+            config.math_frame.center_im = parse(imaginary)?;
+        }
+    }
+    return Ok(config);
+}
+
+/// Copies every value of `from` into `into`, going into tables that both have
+fn merge(into: &mut Value, from: Value) {
+    match (into, from) {
+        (Value::Object(into), Value::Object(from)) => {
+            for (key, value) in from {
+                match into.get_mut(&key) {
+                    Some(existing) => merge(existing, value),
+                    None => { into.insert(key, value); },
+                }
+            }
+        },
+        (into, from) => *into = from,
+    }
+}
+
+/// Function for writing a config as TOML, which `read_config_file` reads back the same
+pub fn dump_config(config: &Config) -> Result<String, KyrosError> {
+    return toml::to_string(config).map_err(|error| KyrosError::Encoding(error.to_string()));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::temp_path;
+
+    /// Reads a config file with `text` in it, from the default config
+    fn read_text(name: &str, text: &str) -> Result<Config, KyrosError> {
+        let path = temp_path(name);
+        fs::write(&path, text).unwrap();
+        let config = read_config_file(&path, &Config::default());
+        fs::remove_file(&path).unwrap();
+        return config;
+    }
+
+    #[test]
+    fn fields_match_the_config() {
+        let Value::Object(config) = serde_json::to_value(Config::default()).unwrap() else { panic!() };
+        assert_eq!(config.keys().count(), CONFIG_FIELDS.len());
+        assert!(config.keys().all(|key| CONFIG_FIELDS.iter().any(|field| field.0 == key)));
+
+        let Value::Object(math_frame) = &config["math_frame"] else { panic!() };
+        assert_eq!(math_frame.keys().count(), MATH_FRAME_FIELDS.len());
+        assert!(math_frame.keys().all(|key| MATH_FRAME_FIELDS.iter().any(|field| field.0 == key)));
+    }
+
+    #[test]
+    fn reads_toml_and_json() {
+        let config = read_text("config.toml", "max_i = 4096\ngen_formula = \"BS\"\n[math_frame]\ncenter_re = -1.75\n").unwrap();
+        assert_eq!((config.max_i, config.gen_formula.as_str(), config.math_frame.center_re), (4096, "BS", -1.75));
+
+        // The exact digits follow the center
+        assert_eq!(config.math_frame.center_exact, Some(("-1.75".to_string(), "0".to_string())));
+
+        let config = read_text("config.json", r#"{ "max_i": 12, "math_frame": { "zoom": 3.0 } }"#).unwrap();
+        assert_eq!((config.max_i, config.math_frame.zoom), (12, 3.0));
+    }
+
+    #[test]
+    fn unknown_fields_are_errors() {
+        let error = read_text("misspelled.toml", "max_it = 5\n").unwrap_err();
+        assert!(matches!(&error, KyrosError::NotFound { name, .. } if name == "max_it"));
+        assert_eq!(error.suggestion(), Some("max_i"));
+
+        let error = read_text("misspelled.json", r#"{ "gen_fromula": "BS" }"#).unwrap_err();
+        assert_eq!(error.suggestion(), Some("gen_formula"));
+
+        let error = read_text("nested.toml", "[math_frame]\nzoon = 3.0\n").unwrap_err();
+        assert_eq!(error.suggestion(), Some("zoom"));
+
+        assert!(read_text("unknown.toml", "iterations = 5\n").is_err());
+    }
+}
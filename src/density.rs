@@ -0,0 +1,210 @@
+#![allow(non_snake_case)]
+
+/*
+  File for density renders (the Buddhabrot & Nebulabrot), where random c values are
+  sampled & every point the orbits of the escaping ones pass through is counted.
+  Up to three bands of escape times are counted separately, one for each of red, green & blue.
+*/
+
+use crate::error::KyrosError;
+use crate::structs::{Complex, Config};
+use crate::utils::{thread_pool, Measuring};
+
+use image::RgbImage;
+use rayon::prelude::*;
+
+use std::io::Write;
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+
+/// Amount of samples every worker takes at a time, each chunk has its own random numbers
+/// so the same seed gives the same image with any amount of threads
+const CHUNK_SAMPLES: u64 = 1 << 16;
+
+/// Half the width of the square the samples are taken from, centered at 0
+const SAMPLE_RADIUS: f64 = 2.0;
+
+/// Small & fast random number generator (splitmix64), so renders don't depend on an outside crate
+pub struct SplitMix64 {
+    state: u64,
+}
+
+impl SplitMix64 {
+    pub fn new(seed: u64) -> SplitMix64 {
+        return SplitMix64 { state: seed };
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E3779B97F4A7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+        return z ^ (z >> 31);
+    }
+
+    /// Gets a number in [0, 1)
+    pub fn next_f64(&mut self) -> f64 {
+        return (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64;
+    }
+}
+
+fn LINEAR(count: f64, max: f64) -> f64 {
+    return count / max;
+}
+
+fn SQRT(count: f64, max: f64) -> f64 {
+    return (count / max).sqrt();
+}
+
+fn LOG(count: f64, max: f64) -> f64 {
+    return count.ln_1p() / max.ln_1p();
+}
+
+/// Type of every tone map, `(count, largest count) -> brightness in [0, 1]`
+pub type ToneFn = dyn Fn(f64, f64) -> f64 + Sync;
+
+/// Sets Bootleg hashmap for tone maps
+pub const TONE_MAPS: [(&str, &ToneFn, &str);3] = [
+    ("LINEAR" , &LINEAR , "Brightness grows with the count, only the brightest paths show"),
+    ("SQRT"   , &SQRT   , "\tBrightness grows with the square root of the count"),
+    ("LOG"    , &LOG    , "\tBrightness grows with the log of the count, shows the faintest paths"),
+];
+
+/// Function for getting the tone map from its name
+pub fn get_tone_map(tone_map: &str) -> Result<&'static ToneFn, KyrosError> {
+
+    // Tries to find function in TONE_MAPS const
+    for (key, value, _) in TONE_MAPS.iter() {
+        if key == &tone_map {
+            return Ok(value);
+        }
+    }
+
+    // If not found return error
+    return Err(KyrosError::not_found(
+        "Tone map",
+        "Tone maps",
+        tone_map,
+        TONE_MAPS.iter().map(|v| (v.0, v.2)),
+    ));
+}
+
+/// Settings of a density render
+pub struct Density {
+    pub samples: u64,            // Amount of random points to run orbits from
+    pub seed: u64,               // Seed of the random points, the same seed gives the same image
+    pub bands: Vec<(u64, u64)>,  // Escape times (min, max) counted for red, green & blue (one band is gray)
+    pub tone_map: String,        // Name of the tone map, from TONE_MAPS
+}
+
+/// Renders the density of the orbits of escaping points, with the formula & viewport of `config`.
+/// Points are sampled from [-2, 2] on both axes, as c (or as the starting z of julia sets).
+pub fn render_density(config: &Config, density: &Density) -> Result<RgbImage, KyrosError> {
+    config.validate()?;
+    if density.bands.is_empty() || density.bands.len() > 3 {
+        return Err(KyrosError::InvalidOption(format!(
+            "Density renders need 1 to 3 bands, {} given!", density.bands.len()
+        )));
+    }
+    if let Some((min, max)) = density.bands.iter().find(|(min, max)| min > max) {
+        return Err(KyrosError::InvalidOption(format!(
+            "Band {},{} is invalid, its min can't be larger than its max!", min, max
+        )));
+    }
+    let tone_map = get_tone_map(density.tone_map.as_str())?;
+    let measuring = Measuring::new(config)?;
+
+    let pixels = config.size_x as usize * config.size_y as usize;
+    let max_i = density.bands.iter().map(|(_, max)| *max).max().unwrap_or(0);
+    let chunks = density.samples.div_ceil(CHUNK_SAMPLES);
+    let chunks_done = AtomicU64::new(0);
+
+    // Every band gets its own histogram, one after the other. All the workers count into
+    // the same one, so the memory used doesn't grow with the amount of threads
+    let histogram: Vec<AtomicU32> = (0..pixels * density.bands.len()).map(|_| AtomicU32::new(0)).collect();
+    thread_pool(config)?.install(|| {
+        (0..chunks)
+            .into_par_iter()
+            .for_each(|chunk| {
+                let mut random = SplitMix64::new(density.seed ^ chunk.wrapping_mul(0xD1B54A32D192ED03));
+                let mut points = Vec::new();
+                let samples = CHUNK_SAMPLES.min(density.samples - chunk * CHUNK_SAMPLES);
+
+                for _ in 0..samples {
+                    let point = Complex {
+                        real: SAMPLE_RADIUS * (2.0 * random.next_f64() - 1.0),
+                        imaginary: SAMPLE_RADIUS * (2.0 * random.next_f64() - 1.0),
+                    };
+                    let Some(steps) = measuring.orbit(config, point, max_i, &mut points) else {
+                        continue;
+                    };
+                    for (band, (min, max)) in density.bands.iter().enumerate() {
+                        if steps < *min || steps > *max {
+                            continue;
+                        }
+                        let counts = &histogram[band * pixels..(band + 1) * pixels];
+                        for point in points.iter() {
+                            let (x, y) = config.complex_to_pixel(*point);
+                            let (x, y) = (x.round(), y.round());
+                            if x >= 0.0 && y >= 0.0 && x < config.size_x as f64 && y < config.size_y as f64 {
+                                let count = &counts[y as usize * config.size_x as usize + x as usize];
+                                let _ = count.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |count| count.checked_add(1));
+                            }
+                        }
+                    }
+                }
+                report_chunks(config, chunks_done.fetch_add(1, Ordering::Relaxed) + 1, chunks);
+            });
+    });
+    let histogram: Vec<u32> = histogram.into_iter().map(AtomicU32::into_inner).collect();
+
+    if config.progress {
+        println!();
+    }
+
+    // Tone maps every band by its own largest count
+    let mut image: RgbImage = image::ImageBuffer::new(config.size_x, config.size_y);
+    for (band, counts) in histogram.chunks_exact(pixels).enumerate() {
+        let max = counts.iter().copied().max().unwrap_or(0).max(1) as f64;
+        for (pixel, count) in image.pixels_mut().zip(counts.iter()) {
+            let value = (255.0 * tone_map(*count as f64, max).clamp(0.0, 1.0)).round() as u8;
+            match density.bands.len() {
+                1 => pixel.0 = [value; 3],
+                _ => pixel.0[band] = value,
+            }
+        }
+    }
+    return Ok(image);
+}
+
+/// Function for showing how many of the chunks of samples are done
+fn report_chunks(config: &Config, done: u64, chunks: u64) {
+    if config.progress {
+        print!("\t {:.2}% | {} / {}\r", 100.0 * done as f64 / chunks as f64, done, chunks);
+        let _ = std::io::stdout().flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn seeds_give_the_same_image() {
+        let config = Config { size_x: 48, size_y: 40, threads: 1, ..Config::default() };
+        let density = |seed| Density {
+            samples: 3 * CHUNK_SAMPLES + 1000, // A last chunk that isn't full
+            seed,
+            bands: vec![(0, 20), (21, 60), (61, 200)],
+            tone_map: "SQRT".to_string(),
+        };
+
+        let image = render_density(&config, &density(7)).unwrap();
+        assert!(image.as_raw().iter().any(|v| *v > 0));
+        assert!(image.as_raw() == render_density(&config, &density(7)).unwrap().as_raw());
+        for threads in [2, 5] {
+            let multi = render_density(&Config { threads, ..config.clone() }, &density(7)).unwrap();
+            assert!(image.as_raw() == multi.as_raw(), "{} threads", threads);
+        }
+        assert!(image.as_raw() != render_density(&config, &density(8)).unwrap().as_raw());
+    }
+}
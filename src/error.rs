@@ -0,0 +1,247 @@
+/*
+  File for the errors the library returns instead of exiting
+*/
+
+use crate::math::expression::{function_string, ParseError};
+
+use std::any::Any;
+use std::fmt;
+use std::io;
+
+/// Every error a render can run into
+#[derive(Debug)]
+pub enum KyrosError {
+    /// A name that isn't in one of the registries (formulas, colors, etc.)
+    NotFound {
+        kind: &'static str,  // What the name was looked up as, such as "Color generation method"
+        group: &'static str, // Heading of the allowed names, such as "Colors"
+        name: String,
+        allowed: Vec<(&'static str, &'static str)>, // Every allowed name with its description
+    },
+    /// A formula expression that couldn't be parsed
+    Expression {
+        text: String,
+        error: ParseError,
+    },
+    /// A formula that has no perturbation version was asked to deep zoom
+    NoDeepZoom(String),
+    /// A viewport or image size that can't be rendered
+    InvalidGeometry(String),
+    /// An option that is out of range or can't be used with the rest of the config
+    InvalidOption(String),
+    /// Reading or writing a file (or std-out) failed
+    Io {
+        path: String,
+        source: io::Error,
+    },
+    /// The image couldn't be encoded
+    Encoding(String),
+    /// A file that was read (such as a saved field) isn't in the expected format
+    InvalidFile {
+        path: String,
+        message: String,
+    },
+    /// Some of the jobs of a batch failed (each was reported on its own)
+    BatchFailed {
+        failed: usize,
+        jobs: usize,
+    },
+    /// The worker threads couldn't be started
+    Threads(String),
+    /// A render panicked, which is a bug (caught so the other jobs of a batch still run)
+    Panicked(String),
+}
+
+impl KyrosError {
+    /// Builds the error for `name` missing from a registry
+    pub fn not_found(
+        kind: &'static str,
+        group: &'static str,
+        name: &str,
+        allowed: impl Iterator<Item = (&'static str, &'static str)>,
+    ) -> KyrosError {
+        return KyrosError::NotFound {
+            kind,
+            group,
+            name: name.to_string(),
+            allowed: allowed.collect(),
+        };
+    }
+
+    /// Builds the error for a render that panicked, from the payload `catch_unwind` gave
+    pub fn panicked(payload: Box<dyn Any + Send>) -> KyrosError {
+        let message = payload.downcast_ref::<&str>()
+            .map(|message| message.to_string())
+            .or_else(|| payload.downcast_ref::<String>().cloned())
+            .unwrap_or_else(|| "unknown panic".to_string());
+        return KyrosError::Panicked(message);
+    }
+
+    /// Builds the error for an I/O failure on `path`
+    pub fn io(path: &str, source: io::Error) -> KyrosError {
+        return KyrosError::Io { path: path.to_string(), source };
+    }
+
+    /// Sorts an error from the png encoder into I/O & encoding failures
+    pub fn from_png(path: &str, error: png::EncodingError) -> KyrosError {
+        return match error {
+            png::EncodingError::IoError(source) => KyrosError::io(path, source),
+            error => KyrosError::Encoding(error.to_string()),
+        };
+    }
+
+    /// Sorts an error from the image crate into I/O & encoding failures
+    pub fn from_image(path: &str, error: image::ImageError) -> KyrosError {
+        return match error {
+            image::ImageError::IoError(source) => KyrosError::io(path, source),
+            error => KyrosError::Encoding(error.to_string()),
+        };
+    }
+
+    /// Gets the allowed name closest to the one that wasn't found, if any is close enough
+    pub fn suggestion(&self) -> Option<&'static str> {
+        let KyrosError::NotFound { name, allowed, .. } = self else {
+            return None;
+        };
+        let name = name.to_uppercase();
+
+        // Allows about one typo for every three characters
+        let max_distance = (name.chars().count() / 3).max(1);
+        return allowed
+            .iter()
+            .map(|v| (edit_distance(&name, &v.0.to_uppercase()), v.0))
+            .filter(|(distance, _)| *distance <= max_distance)
+            .min_by_key(|(distance, _)| *distance)
+            .map(|(_, key)| key);
+    }
+
+    /// Gets the code the CLI exits with for this error.
+    /// 2 for invalid configuration or input files (the same as invalid arguments), 3 for I/O, 4 for encoding
+    /// 5 for batches with failed jobs, 6 when the worker threads couldn't be started, 7 for renders that panicked
+    /// & 8 for options that don't work (such as a bailout too small for the measurement.)
+    pub fn exit_code(&self) -> u8 {
+        return match self {
+            KyrosError::NotFound { .. } |
+            KyrosError::Expression { .. } |
+            KyrosError::NoDeepZoom(_) |
+            KyrosError::InvalidGeometry(_) |
+            KyrosError::InvalidFile { .. } => 2,
+            KyrosError::Io { .. } => 3,
+            KyrosError::Encoding(_) => 4,
+            KyrosError::BatchFailed { .. } => 5,
+            KyrosError::Threads(_) => 6,
+            KyrosError::Panicked(_) => 7,
+            KyrosError::InvalidOption(_) => 8,
+        };
+    }
+}
+
+/// Gets the amount of single character edits between two strings
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+
+    for (i, a_char) in a.chars().enumerate() {
+        let mut current = vec![i + 1; b.len() + 1];
+        for (j, b_char) in b.iter().enumerate() {
+            let substitution = previous[j] + (a_char != *b_char) as usize;
+            current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+        previous = current;
+    }
+    return previous[b.len()];
+}
+
+impl fmt::Display for KyrosError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            KyrosError::NotFound { kind, group, name, allowed } => {
+                let allowed_string: String = allowed
+                    .iter()
+                    .map(|v| format!("  {}\t{}", v.0, v.1))
+                    .collect::<Vec<String>>()
+                    .join("\n");
+                write!(f, "{} '{}' not found!", kind, name)?;
+                if let Some(suggestion) = self.suggestion() {
+                    write!(f, " Did you mean '{}'?", suggestion)?;
+                }
+                return write!(f, "\n\nAllowed {}:\n{}", group, allowed_string);
+            },
+            KyrosError::Expression { text, error } => {
+                // Points at the column the expression went wrong at
+                return write!(
+                    f,
+                    "Formula expression error at column {}: {}\n\n  {}\n  {}^\n\nAllowed Functions:\n{}",
+                    error.column, error.message, text, " ".repeat(error.column - 1), function_string()
+                );
+            },
+            KyrosError::NoDeepZoom(formula) => {
+                return write!(f, "Formula '{}' doesn't support deep zooms, (past f64 precision only SD, R, ABR, BS & SYM render.)", formula);
+            },
+            KyrosError::InvalidGeometry(message) |
+            KyrosError::InvalidOption(message) => {
+                return write!(f, "{}", message);
+            },
+            KyrosError::Io { path, source } => {
+                return write!(f, "Couldn't access '{}': {}", path, source);
+            },
+            KyrosError::Encoding(message) => {
+                return write!(f, "Couldn't encode the image: {}", message);
+            },
+            KyrosError::InvalidFile { path, message } => {
+                return write!(f, "Couldn't read '{}', {}!", path, message);
+            },
+            KyrosError::BatchFailed { failed, jobs } => {
+                return write!(f, "{} of {} batch jobs failed!", failed, jobs);
+            },
+            KyrosError::Threads(message) => {
+                return write!(f, "Couldn't start the worker threads: {}", message);
+            },
+            KyrosError::Panicked(message) => {
+                return write!(f, "The render crashed, which is a bug: {}", message);
+            },
+        }
+    }
+}
+
+impl std::error::Error for KyrosError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        return match self {
+            KyrosError::Io { source, .. } => Some(source),
+            _ => None,
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::math::formula::get_formula;
+    use crate::structs::Config;
+
+    #[test]
+    fn edit_distances() {
+        assert_eq!(edit_distance("", ""), 0);
+        assert_eq!(edit_distance("", "ABC"), 3);
+        assert_eq!(edit_distance("KITTEN", "SITTING"), 3);
+        assert_eq!(edit_distance("NEWTON", "NEWTON"), 0);
+        assert_eq!(edit_distance("NOAV", "NOVA"), 2);
+    }
+
+    #[test]
+    fn suggestions() {
+        let suggestion = |name: &str| get_formula(name).err().unwrap().suggestion();
+        assert_eq!(suggestion("multibrt"), Some("MULTIBROT"));
+        assert_eq!(suggestion("NEWTOM"), Some("NEWTON"));
+        assert_eq!(suggestion("HELP"), None);
+        assert_eq!(KyrosError::InvalidGeometry(String::new()).suggestion(), None);
+    }
+    #[test]
+    fn options_have_their_own_exit_code() {
+        let config = Config { size_x: 0, ..Config::default() };
+        assert_eq!(crate::render(&config).unwrap_err().exit_code(), 2);
+
+        let config = Config { bailout: Some(0.5), measurement: "SMOOTH".to_string(), ..Config::default() };
+        assert_eq!(crate::render(&config).unwrap_err().exit_code(), 8);
+    }
+}
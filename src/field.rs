@@ -0,0 +1,289 @@
+/*
+  File for the measured values of an image, kept apart from their colors.
+
+# Field files
+Fields can be saved as .kyf files, which start with the magic bytes "KYF1",
+then the length of the header as a little endian u64 & the header itself, a
+JSON object holding the full config the field was rendered with. The header
+is followed by the planes it lists, each one f64 per pixel (little endian,
+row by row). The same planes can also be saved as a NumPy .npy file with the
+shape (planes, size_y, size_x).
+*/
+
+use crate::error::KyrosError;
+use crate::math::measurement::Sample;
+use crate::structs::Config;
+
+use serde::{Deserialize, Serialize};
+
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+
+/// Magic bytes every .kyf file starts with
+const KYF_MAGIC: &[u8] = b"KYF1";
+
+/// Magic bytes every .npy file starts with
+const NPY_MAGIC: &[u8] = b"\x93NUMPY";
+
+/// Planes written to field files, in order
+const PLANES: [&str;3] = [
+    "value",   // Value the measurement gave each pixel
+    "steps",   // Amount of steps the orbit took
+    "escaped", // 1 for orbits that escaped, 0 for interior points
+];
+
+/// Header of .kyf files
+#[derive(Serialize, Deserialize)]
+struct KyfHeader {
+    size_x: u32,
+    size_y: u32,
+    planes: Vec<String>,
+    config: Config,
+}
+
+/// Measured value of every pixel of an image, stored row by row.
+/// Measuring is the expensive part of a render, so one field can be colored many times over.
+#[derive(Debug, Clone)]
+pub struct Field {
+    pub size_x: u32,
+    pub size_y: u32,
+    pub samples: Vec<Sample>,
+}
+
+impl Field {
+    /// Gets a field of the given size with every sample unset
+    pub fn new(size_x: u32, size_y: u32) -> Field {
+        return Field {
+            size_x,
+            size_y,
+            samples: vec![Sample::default(); size_x as usize * size_y as usize],
+        };
+    }
+
+    /// Gets the sample of pixel (x, y)
+    pub fn get(&self, x: u32, y: u32) -> Sample {
+        return self.samples[y as usize * self.size_x as usize + x as usize];
+    }
+
+    /// Saves the field as a .kyf file, along with the config it was rendered with
+    pub fn write_kyf(&self, config: &Config, path: &str) -> Result<(), KyrosError> {
+        let header = serde_json::to_vec(&KyfHeader {
+            size_x: self.size_x,
+            size_y: self.size_y,
+            planes: PLANES.iter().map(|v| v.to_string()).collect(),
+            config: config.clone(),
+        }).map_err(|error| KyrosError::Encoding(error.to_string()))?;
+
+        let mut out = Vec::with_capacity(KYF_MAGIC.len() + 8 + header.len());
+        out.extend_from_slice(KYF_MAGIC);
+        out.extend_from_slice(&(header.len() as u64).to_le_bytes());
+        out.extend_from_slice(&header);
+        return self.write_planes(path, &out);
+    }
+
+    /// Saves the field as a NumPy .npy file
+    pub fn write_npy(&self, path: &str) -> Result<(), KyrosError> {
+        let mut header = format!(
+            "{{'descr': '<f8', 'fortran_order': False, 'shape': ({}, {}, {}), }}",
+            PLANES.len(), self.size_y, self.size_x
+        );
+
+        // Pads the header so the data starts on a multiple of 64 bytes
+        let unpadded = NPY_MAGIC.len() + 4 + header.len() + 1;
+        header.push_str(&" ".repeat(unpadded.next_multiple_of(64) - unpadded));
+        header.push('\n');
+
+        let mut out = Vec::with_capacity(NPY_MAGIC.len() + 4 + header.len());
+        out.extend_from_slice(NPY_MAGIC);
+        out.extend_from_slice(&[1, 0]);
+        out.extend_from_slice(&(header.len() as u16).to_le_bytes());
+        out.extend_from_slice(header.as_bytes());
+        return self.write_planes(path, &out);
+    }
+
+    /// Writes `header` followed by every plane of the field
+    fn write_planes(&self, path: &str, header: &[u8]) -> Result<(), KyrosError> {
+        let io_error = |error| KyrosError::io(path, error);
+        let mut writer = BufWriter::new(File::create(path).map_err(io_error)?);
+        writer.write_all(header).map_err(io_error)?;
+
+        let planes: [&dyn Fn(&Sample) -> f64;3] = [
+            &|sample| sample.value,
+            &|sample| sample.steps as f64,
+            &|sample| sample.escaped as u8 as f64,
+        ];
+        for plane in planes.iter() {
+            for sample in self.samples.iter() {
+                writer.write_all(&plane(sample).to_le_bytes()).map_err(io_error)?;
+            }
+        }
+        return writer.flush().map_err(io_error);
+    }
+
+    /// Reads the field back from the planes following the header of a file
+    fn from_planes(size_x: u32, size_y: u32, data: &[u8]) -> Result<Field, String> {
+        // The sizes come from the file, so a corrupted one can ask for more bytes than there are
+        let too_large = || format!("a {}x{} field is too large to be read", size_x, size_y);
+        let pixels = (size_x as usize).checked_mul(size_y as usize).ok_or_else(too_large)?;
+        let length = pixels.checked_mul(PLANES.len() * 8).ok_or_else(too_large)?;
+        if data.len() != length {
+            return Err(format!("expected {} bytes of data for a {}x{} field, found {}", length, size_x, size_y, data.len()));
+        }
+        let plane = |index: usize, pixel: usize| {
+            let start = (index * pixels + pixel) * 8;
+            f64::from_le_bytes(data[start..start + 8].try_into().unwrap())
+        };
+
+        let mut field = Field::new(size_x, size_y);
+        for (pixel, sample) in field.samples.iter_mut().enumerate() {
+            *sample = Sample {
+                value: plane(0, pixel),
+                steps: plane(1, pixel) as u64,
+                escaped: plane(2, pixel) != 0.0,
+            };
+        }
+        return Ok(field);
+    }
+}
+
+/// Reads the parts of a .kyf file
+fn parse_kyf(bytes: &[u8]) -> Result<(Config, Field), String> {
+    let header_length = bytes.get(4..12).ok_or("the header is missing")?;
+    let header_end = 12usize.saturating_add(u64::from_le_bytes(header_length.try_into().unwrap()) as usize);
+    let header = bytes.get(12..header_end).ok_or("the header is cut short")?;
+    let header: KyfHeader = serde_json::from_slice(header).map_err(|error| format!("the header is invalid, {}", error))?;
+
+    if header.planes != PLANES {
+        return Err(format!("expected the planes {:?}, found {:?}", PLANES, header.planes));
+    }
+    let field = Field::from_planes(header.size_x, header.size_y, &bytes[header_end..])?;
+    return Ok((header.config, field));
+}
+
+/// Reads the parts of a .npy file, which has to hold f64 planes the way `write_npy` saves them
+fn parse_npy(bytes: &[u8]) -> Result<Field, String> {
+    let version = bytes.get(6).ok_or("the header is missing")?;
+    let (length_size, header_start) = match version {
+        1 => (2, 10),
+        _ => (4, 12),
+    };
+    let header_length = bytes.get(8..8 + length_size).ok_or("the header is missing")?;
+    let header_length = header_length.iter().rev().fold(0usize, |length, byte| (length << 8) | *byte as usize);
+    let header = bytes.get(header_start..header_start + header_length).ok_or("the header is cut short")?;
+    let header = String::from_utf8_lossy(header).replace(' ', "");
+
+    if !header.contains("'descr':'<f8'") || !header.contains("'fortran_order':False") {
+        return Err("only little endian f64 arrays in C order can be read".to_string());
+    }
+
+    // Reads the numbers of the shape tuple, such as "(3,512,512)"
+    let shape = header
+        .split("'shape':(")
+        .nth(1)
+        .and_then(|rest| rest.split(')').next())
+        .ok_or("the shape is missing")?;
+    let shape: Vec<u32> = shape
+        .split(',')
+        .filter(|v| !v.is_empty())
+        .map(|v| v.parse::<u32>())
+        .collect::<Result<Vec<u32>, _>>()
+        .map_err(|_| format!("the shape '({})' is invalid", shape))?;
+
+    match shape[..] {
+        [planes, size_y, size_x] if planes as usize == PLANES.len() => {
+            return Field::from_planes(size_x, size_y, &bytes[header_start + header_length..]);
+        },
+        _ => return Err(format!("expected a shape of ({}, height, width), found {:?}", PLANES.len(), shape)),
+    }
+}
+
+/// Reads a field saved as .kyf (along with the config it was rendered with) or .npy
+pub fn read_field(path: &str) -> Result<(Option<Config>, Field), KyrosError> {
+    let bytes = fs::read(path).map_err(|error| KyrosError::io(path, error))?;
+    let invalid = |message| KyrosError::InvalidFile { path: path.to_string(), message };
+
+    if bytes.starts_with(KYF_MAGIC) {
+        let (config, field) = parse_kyf(&bytes).map_err(invalid)?;
+        return Ok((Some(config), field));
+    }
+    if bytes.starts_with(NPY_MAGIC) {
+        let field = parse_npy(&bytes).map_err(invalid)?;
+        return Ok((None, field));
+    }
+    return Err(invalid("it isn't a .kyf or .npy file".to_string()));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::temp_path;
+
+    /// Field with a different sample in every pixel, including the awkward values
+    fn test_field() -> Field {
+        let mut field = Field::new(3, 2);
+        let values = [0.0, -1.5, 1e300, f64::NAN, f64::INFINITY, 12.25];
+        for (i, (sample, value)) in field.samples.iter_mut().zip(values).enumerate() {
+            *sample = Sample { value, steps: 7 * i as u64, escaped: i % 2 == 0 };
+        }
+        return field;
+    }
+
+    fn assert_same(a: &Field, b: &Field) {
+        assert_eq!((a.size_x, a.size_y), (b.size_x, b.size_y));
+        for (a, b) in a.samples.iter().zip(b.samples.iter()) {
+            assert_eq!(a.value.to_bits(), b.value.to_bits());
+            assert_eq!((a.steps, a.escaped), (b.steps, b.escaped));
+        }
+    }
+
+    #[test]
+    fn kyf_round_trip() {
+        let path = temp_path("field.kyf");
+        let config = Config { size_x: 3, size_y: 2, max_i: 321, color_formula: "SINUSOIDAL".to_string(), ..Config::default() };
+        let field = test_field();
+        field.write_kyf(&config, &path).unwrap();
+
+        let (read_config, read) = read_field(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        let read_config = read_config.unwrap();
+        assert_eq!(read_config.max_i, 321);
+        assert_eq!(read_config.color_formula, "SINUSOIDAL");
+        assert_same(&field, &read);
+    }
+
+    #[test]
+    fn npy_round_trip() {
+        let path = temp_path("field.npy");
+        let field = test_field();
+        field.write_npy(&path).unwrap();
+
+        let (config, read) = read_field(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert!(config.is_none());
+        assert_same(&field, &read);
+    }
+
+    #[test]
+    fn truncated_files_are_errors() {
+        let field = test_field();
+        let config = Config { size_x: 3, size_y: 2, ..Config::default() };
+        let path = temp_path("truncated.kyf");
+        field.write_kyf(&config, &path).unwrap();
+        let bytes = fs::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert!(parse_kyf(&bytes[..bytes.len() - 8]).is_err());
+        assert!(parse_kyf(&bytes[..10]).is_err());
+        assert!(parse_npy(b"\x93NUMPY").is_err());
+
+        // Sizes that overflow the amount of bytes they need
+        let header = "{'descr': '<f8', 'fortran_order': False, 'shape': (3, 4294967295, 4294967295), }";
+        let mut bytes = b"\x93NUMPY\x01\x00".to_vec();
+        bytes.extend_from_slice(&(header.len() as u16).to_le_bytes());
+        bytes.extend_from_slice(header.as_bytes());
+        bytes.extend_from_slice(&[0; 24]);
+        assert!(parse_npy(&bytes).unwrap_err().contains("too large"));
+    }
+}
@@ -0,0 +1,63 @@
+/*
+  File for rendering atlases of Julia sets, with a small Julia set for every
+  constant sampled on a grid across a region of the parameter plane.
+*/
+
+use crate::error::KyrosError;
+use crate::structs::{Config, MathFrame};
+use crate::utils::{color_field, measure_field, thread_pool};
+
+use image::RgbImage;
+
+/// Renders a `columns` by `rows` grid of Julia sets into one image.
+/// Every tile is rendered with the size & viewport of `config`, using the constant that the
+/// pixel in its place would get if `region` of the parameter plane was rendered `columns` by `rows`
+/// pixels large, so the tiles are laid out the same way the region itself would be.
+pub fn julia_grid(config: &Config, columns: u32, rows: u32, region: &MathFrame) -> Result<RgbImage, KyrosError> {
+    config.validate()?;
+    if columns == 0 || rows == 0 {
+        return Err(KyrosError::InvalidGeometry(format!(
+            "A grid of {}x{} tiles is invalid, it needs at least one column & row!", columns, rows
+        )));
+    }
+
+    // The grid of constants, as an image with one pixel for every tile
+    let grid = Config {
+        size_x: columns,
+        size_y: rows,
+        math_frame: region.clone(),
+        ..config.clone()
+    };
+    grid.validate()?;
+
+    let too_large = || KyrosError::InvalidGeometry(format!(
+        "A {}x{} grid of {}x{} tiles is too large for one image!", columns, rows, config.size_x, config.size_y
+    ));
+    let atlas_x = columns.checked_mul(config.size_x).ok_or_else(too_large)?;
+    let atlas_y = rows.checked_mul(config.size_y).ok_or_else(too_large)?;
+
+    let pool = thread_pool(config)?;
+    let mut atlas: RgbImage = image::ImageBuffer::new(atlas_x, atlas_y);
+
+    for row in 0..rows {
+        for column in 0..columns {
+            if config.progress {
+                println!("Tile {} / {}", row * columns + column + 1, columns * rows);
+            }
+            let tile_config = Config {
+                c_init: Some(grid.pixel_to_complex(column as f64, row as f64)),
+                ..config.clone()
+            };
+            let field = measure_field(&tile_config, &pool)?;
+            let tile = color_field(&tile_config, &pool, &field)?;
+
+            image::imageops::replace(
+                &mut atlas,
+                &tile,
+                (column * config.size_x) as i64,
+                (row * config.size_y) as i64,
+            );
+        }
+    }
+    return Ok(atlas);
+}
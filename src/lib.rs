@@ -0,0 +1,69 @@
+#![allow(clippy::needless_return)]
+
+/*
+  Library root, for rendering fractals from other programs.
+  The CLI in main.rs is only a wrapper around what is here.
+*/
+
+// Project Crates
+pub mod math;
+pub mod structs;
+pub mod colors;
+pub mod save;
+pub mod error;
+pub mod animation;
+pub mod field;
+pub mod metadata;
+pub mod config_file;
+pub mod batch;
+pub mod julia_grid;
+pub mod density;
+mod utils;
+#[cfg(test)]
+mod test_utils;
+
+pub use crate::structs::{Complex, Config, MathFrame};
+pub use crate::error::KyrosError;
+pub use crate::field::Field;
+
+pub use crate::math::formula::{get_formula, Formula, FormulaFn, FORMULAS};
+pub use crate::math::measurement::{get_measurement, Measurement, Sample, MEASUREMENTS};
+pub use crate::math::measurement::{get_escape_norm, EscapeFn, ESCAPE_NORMS};
+pub use crate::math::interior::{get_interior, Attractor, InteriorFn, INTERIORS};
+pub use crate::colors::color::{get_color, ColorFn, COLORS};
+pub use crate::colors::color::{get_instant_escape, InstantEscape, INSTANT_ESCAPES};
+pub use crate::colors::colormap::{get_colormap, get_normalization, Colormap, COLORMAPS, Normalization, NORMALIZATIONS};
+pub use crate::colors::shadows::{get_shadow, ShadowFn, SHADOWS};
+pub use crate::save::{get_save_method, SaveFn, SAVE_METHODS};
+
+/// Renders the image described by `config`.
+/// Unknown names in the config (formula, color, etc.) are returned as errors.
+pub fn render(config: &Config) -> Result<image::RgbImage, KyrosError> {
+    return utils::eval_function(config);
+}
+
+/// Measures every pixel of the image described by `config`, without coloring it.
+/// This is the expensive part of a render, see `color` for the cheap part.
+pub fn measure(config: &Config) -> Result<Field, KyrosError> {
+    return utils::measure_field(config, &*utils::thread_pool(config)?);
+}
+
+/// Colors a measured field with the color & shadow functions (and hue offset) of `config`
+pub fn color(config: &Config, field: &Field) -> Result<image::RgbImage, KyrosError> {
+    return utils::color_field(config, &*utils::thread_pool(config)?, field);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_config_renders() {
+        let image = render(&Config::default()).unwrap();
+        assert_eq!(image.dimensions(), (256, 256));
+
+        // The corners escape at once & the origin never does
+        assert_eq!(image.get_pixel(0, 0).0, [255, 255, 255]);
+        assert_eq!(image.get_pixel(128, 128).0, [0, 0, 0]);
+    }
+}
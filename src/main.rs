@@ -1,3 +1,5 @@
+#![allow(clippy::needless_return)]
+
 /*
 Author : Mark T
   Date : 6/21/2023
@@ -6,68 +8,380 @@ Author : Mark T
 */
 
 // Project Crates
-mod math;
-mod structs;
-mod colors;
-mod utils;
 mod cli;
-mod save;
-
-use crate::structs::{Complex, Config, MathFrame};
-use crate::cli::Args;
-
-use crate::colors::color::get_color;
-use crate::colors::shadows::get_shadow;
-use crate::math::formula::get_formula;
-use crate::save::get_save_method;
-
-use crate::utils::eval_function;
 
+use kyros::{Complex, Config, MathFrame, KyrosError};
+use kyros::get_save_method;
+use kyros::animation::{animate, color_cycle, Animation};
+use kyros::field::read_field;
+use kyros::metadata::read_config;
+use kyros::config_file::{dump_config, read_config_file};
+use kyros::save::{check_save_image, save_image, save_png};
+use kyros::batch::{batch_summary, run_batch};
+use kyros::julia_grid::julia_grid;
+use kyros::density::{render_density, Density};
+use crate::cli::{Args, Command, AnimateArgs, ReproduceArgs};
 
 // std imports
 use std::env;
+use std::path::Path;
+use std::process::ExitCode;
 use std::time::{SystemTime, UNIX_EPOCH};
 
 // External Imports
 extern crate image;
 
 // External Crates
-use clap::Parser;
+use clap::{CommandFactory, FromArgMatches};
+use clap::error::ErrorKind;
+use clap::parser::ValueSource;
+
+/// Constant of Julia sets when no other is given
+const JULIA_C: Complex = Complex {
+    real: 0.08004012786314796,
+    imaginary: -0.6359321976472476,
+};
+
+/// Parses a number given on the command line, exiting if it isn't one
+fn parse_number(name: &str, value: &str) -> f64 {
+    match value.trim().parse::<f64>() {
+        Ok(number) if number.is_finite() => return number,
+        _ => Args::command().error(
+            ErrorKind::ValueValidation,
+            format!("{} '{}' is not a number!", name, value)
+        ).exit(),
+    }
+}
+
+/// Parses two numbers given on the command line as A,B, exiting if they aren't
+fn parse_pair(name: &str, value: &str) -> (f64, f64) {
+    let parts: Vec<&str> = value.split(',').collect();
+    if parts.len() != 2 {
+        Args::command().error(
+            ErrorKind::ValueValidation,
+            format!("{} '{}' is invalid, it must be written as two numbers split by a comma!", name, value)
+        ).exit();
+    }
+    return (parse_number(name, parts[0]), parse_number(name, parts[1]));
+}
+
+/// Gets the keyframes of an animation, starting at the viewport of `config`
+/// unless a list of keyframes is given
+fn keyframes(config: &Config, args: &AnimateArgs) -> Vec<MathFrame> {
+    if args.keyframes.is_empty() {
+        let start = config.math_frame.clone();
+        let (start_re, start_im) = start.center_exact
+            .clone()
+            .unwrap_or((start.center_re.to_string(), start.center_im.to_string()));
+        let end_re = args.end_center_re.clone().unwrap_or(start_re);
+        let end_im = args.end_center_im.clone().unwrap_or(start_im);
+        let end = MathFrame {
+            center_re: parse_number("Center value", &end_re),
+            center_im: parse_number("Center value", &end_im),
+            center_exact: Some((end_re, end_im)),
+            zoom: args.end_zoom.unwrap_or(start.zoom),
+            rotation: args.end_rotation.unwrap_or(start.rotation),
+        };
+        return vec![start, end];
+    }
+
+    return args.keyframes
+        .iter()
+        .map(|keyframe| {
+            let parts: Vec<&str> = keyframe.split(',').map(|v| v.trim()).collect();
+            if parts.len() != 3 && parts.len() != 4 {
+                Args::command().error(
+                    ErrorKind::ValueValidation,
+                    format!("Keyframe '{}' is invalid, it must be written as RE,IM,ZOOM[,ROTATION]!", keyframe)
+                ).exit();
+            }
+            MathFrame {
+                center_re: parse_number("Center value", parts[0]),
+                center_im: parse_number("Center value", parts[1]),
+                center_exact: Some((parts[0].to_string(), parts[1].to_string())),
+                zoom: parse_number("Zoom", parts[2]),
+                rotation: parts.get(3).map_or(0.0, |v| parse_number("Rotation", v)),
+            }
+        })
+        .collect();
+}
+
+/// Gets the config to reproduce an image with, from the config stored in it
+fn reproduce_config(config: &Config, stored: Config, args: &ReproduceArgs) -> Config {
+    let aspect_ratio = stored.size_x as f64 / stored.size_y as f64;
+    let scale = args.scale.unwrap_or(1.0);
+    let (size_x, size_y) = match (args.width, args.height) {
+        (Some(width), Some(height)) => (width, height),
+        (Some(width), None) => (width, (width as f64 / aspect_ratio).round() as u32),
+        (None, Some(height)) => ((height as f64 * aspect_ratio).round() as u32, height),
+        (None, None) => ((stored.size_x as f64 * scale).round() as u32, (stored.size_y as f64 * scale).round() as u32),
+    };
+
+    // Never overwrites the image being reproduced (or any other)
+    let numbered = |count| Config { count, output: None, ..Config::default() };
+    let count = (stored.count..)
+        .find(|count| !Path::new(&numbered(*count).output_path("png")).exists())
+        .unwrap_or(stored.count);
+
+    return Config {
+        count,
+        output: None,
+        size_x,
+        size_y,
+        progress: config.progress,
+        threads: config.threads,
+        ..stored
+    };
+}
+
+/// Sets the config from every flag that `given` is true for (by its argument id)
+fn apply_args(config: &mut Config, args: &Args, given: &dyn Fn(&str) -> bool) {
+    if given("pixels") {
+        config.size_x = args.pixels;
+        config.size_y = args.pixels;
+    }
+    if let Some(width) = args.width {
+        config.size_x = width;
+    }
+    if let Some(height) = args.height {
+        config.size_y = height;
+    }
+
+    // The digits of the center are kept as given, for deep zooms
+    let frame = &mut config.math_frame;
+    if given("center_re") || given("center_im") {
+        let (mut exact_re, mut exact_im) = frame.center_exact
+            .clone()
+            .unwrap_or((frame.center_re.to_string(), frame.center_im.to_string()));
+        if given("center_re") {
+            frame.center_re = parse_number("Center value", &args.center_re);
+            exact_re = args.center_re.clone();
+        }
+        if given("center_im") {
+            frame.center_im = parse_number("Center value", &args.center_im);
+            exact_im = args.center_im.clone();
+        }
+        frame.center_exact = Some((exact_re, exact_im));
+    }
+    if given("zoom") { frame.zoom = args.zoom; }
+    if given("rotation") { frame.rotation = args.rotation; }
+
+    if given("iterations") { config.max_i = args.iterations; }
+    if given("formula") {
+        config.gen_formula = args.formula.clone();
+        config.formula_expr = None;
+    }
+    if let Some(formula_expr) = &args.formula_expr {
+        config.formula_expr = Some(formula_expr.clone());
+    }
+    if let Some(polynomial) = &args.polynomial {
+        config.polynomial = Some(polynomial.split(',').map(|v| parse_number("Polynomial coefficient", v)).collect());
+    }
+    if let Some(power) = args.power {
+        let mut polynomial = vec![0.0; power as usize + 1];
+        polynomial[0] = 1.0;
+        config.polynomial = Some(polynomial);
+    }
+    if given("color") { config.color_formula = args.color.clone(); }
+    if given("hue_offset") { config.hue_offset = args.hue_offset; }
+    if let Some(palette) = &args.palette {
+        config.palette = Some(palette.clone());
+    }
+    if given("palette_offset") { config.palette_offset = args.palette_offset; }
+    if let Some(palette_scale) = args.palette_scale {
+        config.palette_scale = Some(palette_scale);
+    }
+    if let Some(normalize) = &args.normalize {
+        config.normalization = Some(normalize.clone());
+    }
+    if given("shadow") { config.shadow_formula = args.shadow.clone(); }
+    if given("measure") { config.measurement = args.measure.clone(); }
+    if given("travel_distance") && args.travel_distance {
+        config.measurement = "TRAVEL_DISTANCE".to_string();
+    }
+    if given("smooth") && args.smooth {
+        config.measurement = "SMOOTH".to_string();
+    }
+    if let Some(bailout) = args.bailout {
+        config.bailout = Some(bailout);
+    }
+    if let Some(escape_norm) = &args.escape_norm {
+        config.escape_norm = Some(escape_norm.clone());
+    }
+    if let Some(interior) = &args.interior {
+        config.interior = Some(interior.clone());
+    }
+    if let Some(instant_escape) = &args.instant_escape {
+        config.instant_escape = Some(instant_escape.clone());
+    }
+    if given("save_method") { config.save_method = args.save_method.clone(); }
+    if let Some(output) = &args.output {
+        config.output = Some(output.clone());
+    }
+    if given("progress") { config.progress = args.progress; }
+    if given("threads") { config.threads = args.threads; }
+    if given("band_rows") { config.band_rows = args.band_rows; }
+    if given("fps") { config.fps = args.fps; }
+    if given("deep") { config.deep_zoom = args.deep; }
+
+    if given("julia") && args.julia {
+        config.c_init = Some(JULIA_C);
+    }
+    if let Some(julia_c) = &args.julia_c {
+        let (real, imaginary) = parse_pair("Julia constant", julia_c);
+        config.c_init = Some(Complex { real, imaginary });
+    }
+    if let Some(julia_c_polar) = &args.julia_c_polar {
+        let (radius, theta) = parse_pair("Julia constant", julia_c_polar);
+        let (sin, cos) = theta.to_radians().sin_cos();
+        config.c_init = Some(Complex { real: radius * cos, imaginary: radius * sin });
+    }
+}
+
+/// Reports errors the same way as invalid arguments, with an exit code for the kind of error
+fn report_error(error: &KyrosError) -> ExitCode {
+    let _ = Args::command().error(ErrorKind::InvalidValue, error).print();
+    return ExitCode::from(error.exit_code());
+}
+
+/// Renders & saves everything the command line asks for
+fn run(config: &Config, command: &Option<Command>) -> Result<(), KyrosError> {
+
+    // Sets the save method before generation (For ensuring this is tested before the image is
+    // generated)
+    let save_method = get_save_method(config.save_method.as_str())?;
+
+    match command {
+        Some(Command::Animate(args)) => {
+            let animation = Animation::new(keyframes(config, args), args.frames, &args.easing)?;
+            return animate(config, &animation);
+        },
+        Some(Command::Cycle(args)) => return color_cycle(config, args.frames),
+        Some(Command::Recolor(args)) => {
+            // Colors from the command line, everything else from the file (when it has a config)
+            let (file_config, field) = read_field(&args.file)?;
+            let recolor_config = Config {
+                count: config.count,
+                color_formula: config.color_formula.clone(),
+                shadow_formula: config.shadow_formula.clone(),
+                hue_offset: config.hue_offset,
+                palette: config.palette.clone(),
+                palette_offset: config.palette_offset,
+                palette_scale: config.palette_scale,
+                normalization: config.normalization.clone(),
+                instant_escape: config.instant_escape.clone(),
+                progress: config.progress,
+                threads: config.threads,
+                size_x: field.size_x,
+                size_y: field.size_y,
+                ..file_config.unwrap_or(config.clone())
+            };
+            let image = kyros::color(&recolor_config, &field)?;
+            return save_png(&recolor_config, &image);
+        },
+        Some(Command::Reproduce(args)) => {
+            let stored = read_config(&args.file)?;
+            let reproduce_config = reproduce_config(config, stored, args);
+            return get_save_method(reproduce_config.save_method.as_str())?(&reproduce_config);
+        },
+        Some(Command::JuliaGrid(args)) => {
+            let region = MathFrame {
+                center_re: args.c_center_re,
+                center_im: args.c_center_im,
+                center_exact: None,
+                zoom: args.c_zoom,
+                rotation: 0.0,
+            };
+            check_save_image(config)?;
+            let atlas = julia_grid(config, args.columns, args.rows, &region)?;
+            let atlas_config = Config {
+                size_x: atlas.width(),
+                size_y: atlas.height(),
+                ..config.clone()
+            };
+            return save_image(&atlas_config, &atlas);
+        },
+        Some(Command::Buddhabrot(args)) => {
+            let bands = match args.bands.is_empty() {
+                true => vec![(0, config.max_i)],
+                false => args.bands
+                    .iter()
+                    .map(|band| {
+                        let (min, max) = parse_pair("Band", band);
+                        (min.max(0.0) as u64, max.max(0.0) as u64)
+                    })
+                    .collect(),
+            };
+            let density = Density {
+                samples: args.samples,
+                seed: args.seed,
+                bands,
+                tone_map: args.tone_map.clone(),
+            };
+            let image = render_density(config, &density)?;
+            return save_png(config, &image);
+        },
+        Some(Command::Batch(args)) => {
+            let reports = run_batch(&args.file, config)?;
+            print!("{}", batch_summary(&reports));
+
+            let failed = reports.iter().filter(|report| report.error.is_some()).count();
+            if failed > 0 {
+                return Err(KyrosError::BatchFailed { failed, jobs: reports.len() });
+            }
+            return Ok(());
+        },
+        None => return save_method(config),
+    }
+}
 
 /// Main function of the program
-fn main() {
+fn main() -> ExitCode {
 
     // env::set_var("RUST_BACKTRACE", "1");
     env::set_var("RUST_BACKTRACE", "full");
 
     // Defines values from CLI arguments
-    let cli_args = Args::parse();
-
-    let mut config = Config {
-        count: 0,
-        c_init: None,
-        size_x: cli_args.pixels,
-        size_y: cli_args.pixels,
-        max_i: cli_args.iterations,
-        gen_formula: cli_args.formula,
-        color_formula: cli_args.color,
-        shadow_formula: cli_args.shadow,
-        travel_distance: cli_args.travel_distance,
-        save_method: cli_args.save_method,
-        math_frame: MathFrame {
-            x_math_space_factor: 4.0 / (cli_args.pixels as f64 - 1.0),
-            x_math_space_offset: 2.0,
-            y_math_space_factor: 4.0 / (cli_args.pixels as f64 - 1.0),
-            y_math_space_offset: 2.0,
-        },
-        progress: cli_args.progress,
-    };
+    let matches = Args::command().get_matches();
+    let cli_args = Args::from_arg_matches(&matches).unwrap_or_else(|error| error.exit());
+
+    // Every flag sets the config, then a config file replaces it
+    // & the flags given on the command line are set again on top
+    let mut config = Config::default();
+    apply_args(&mut config, &cli_args, &|_| true);
+
+    if let Some(path) = &cli_args.config {
+        config = match read_config_file(path, &config) {
+            Ok(config) => config,
+            Err(error) => return report_error(&error),
+        };
+        apply_args(&mut config, &cli_args, &|id| matches.value_source(id) == Some(ValueSource::CommandLine));
+    }
+
+    if cli_args.travel_distance || cli_args.smooth {
+        eprintln!(
+            "Warning: the '{}' flag is deprecated, use '--measure {}' instead",
+            if cli_args.smooth { "smooth" } else { "travel-distance" },
+            config.measurement
+        );
+    }
+
+    // The power sets the polynomial, which every polynomial formula (such as NEWTON) would pick up
+    if cli_args.power.is_some() && (config.formula_expr.is_some() || config.gen_formula != "MULTIBROT") {
+        return report_error(&KyrosError::InvalidOption(format!(
+            "The power flag only works with the MULTIBROT formula, not {}!",
+            config.formula_expr.as_deref().unwrap_or(config.gen_formula.as_str())
+        )));
+    }
 
-    if cli_args.julia {
-        config.c_init = Some(Complex {
-            real: 0.08004012786314796,
-            imaginary: -0.6359321976472476,
-        });
+    if cli_args.dump_config {
+        return match dump_config(&config) {
+            Ok(text) => {
+                print!("{}", text);
+                ExitCode::SUCCESS
+            },
+            Err(error) => report_error(&error),
+        };
     }
 
     // Sets the starting time
@@ -76,19 +390,13 @@ fn main() {
         .unwrap()
         .as_secs_f64();
 
-    // Sets the save method before generation (For ensuring this is tested before the image is
-    // generated)
-    let save_method = get_save_method(&config.save_method.as_str());
+    // Renders & Saves Image
+    let result = run(&config, &cli_args.command);
 
-    // Runs Config, gets 32 byte img object
-    let img = eval_function(&config);
-    if config.progress {
-        println!("Saving File...");
+    if let Err(error) = result {
+        return report_error(&error);
     }
 
-    // Saves Image
-    let _ = save_method(img, &config).unwrap();
-
     // img.save(format!("out#{}.png", config.count)).unwrap();
 
     // Finished Timings
@@ -102,4 +410,26 @@ fn main() {
         println!("[Finished in {:.2}s]", end_time - start_time);
     }
     // let _ = std::io::stdin().read_line(&mut String::new());
+    return ExitCode::SUCCESS;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    #[test]
+    fn animates_from_f64_centers() {
+        // Config files & the library can set a center without its exact digits
+        let config = Config {
+            math_frame: MathFrame { center_re: -0.75, center_im: 0.1, center_exact: None, ..MathFrame::default() },
+            ..Config::default()
+        };
+        let args = Args::try_parse_from(["kyros", "-y", "animate", "--frames", "2", "--end-zoom", "4"]).unwrap();
+        let Some(Command::Animate(args)) = &args.command else { panic!("{:?}", args.command) };
+
+        let frames = keyframes(&config, args);
+        assert_eq!((frames[1].center_re, frames[1].center_im, frames[1].zoom), (-0.75, 0.1, 4.0));
+        assert!(Animation::new(frames, args.frames, &args.easing).is_ok());
+    }
 }
@@ -0,0 +1,430 @@
+/*
+  File for the arbitrary precision numbers used by deep zooms
+*/
+
+use super::super::structs::Complex;
+
+use std::cmp::Ordering;
+use std::ops::{ Add, Sub, Mul };
+
+/// Amount of 32 bit limbs kept in front of the binary point
+const INT_LIMBS: usize = 2;
+
+/// Most decimal digits a parsed number can have in front of the point, so it fits in the integer limbs
+const MAX_INT_DIGITS: i64 = 19;
+
+/// Arbitrary precision fixed point number.
+/// The magnitude is stored as little endian 32 bit limbs, of which the
+/// lowest `frac_limbs` are the fraction. Every number taking part in one
+/// calculation needs the same `frac_limbs`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BigFloat {
+    negative: bool,
+    limbs: Vec<u32>,
+    frac_limbs: usize,
+}
+
+impl BigFloat {
+
+    /// Gets zero with `frac_limbs` limbs of fraction
+    pub fn zero(frac_limbs: usize) -> BigFloat {
+        return BigFloat {
+            negative: false,
+            limbs: vec![0; frac_limbs + INT_LIMBS],
+            frac_limbs,
+        };
+    }
+
+    /// Gets the amount of fraction limbs needed for `bits` bits of fraction
+    pub fn limbs_for_bits(bits: u32) -> usize {
+        return (bits as usize).div_ceil(32).max(2);
+    }
+
+    /// Converts an f64 exactly (as long as it fits in the precision)
+    pub fn from_f64(value: f64, frac_limbs: usize) -> BigFloat {
+        return BigFloat::from_scaled_f64(value, 0, frac_limbs);
+    }
+
+    /// Converts `value * 2^power` exactly (as long as it fits in the precision)
+    pub fn from_scaled_f64(value: f64, power: i64, frac_limbs: usize) -> BigFloat {
+        let mut out = BigFloat::zero(frac_limbs);
+        if value == 0.0 || !value.is_finite() {
+            return out;
+        }
+        out.negative = value < 0.0;
+
+        // Splits the value into a 53 bit integer mantissa & a power of 2
+        let bits = value.abs().to_bits();
+        let exponent = ((bits >> 52) & 0x7ff) as i64;
+        let (mantissa, shift) = match exponent {
+            0 => (bits & 0xf_ffff_ffff_ffff, -1074),
+            _ => ((bits & 0xf_ffff_ffff_ffff) | (1 << 52), exponent - 1075),
+        };
+
+        // Places every set bit of the mantissa at its position in the limbs
+        let bit_offset = frac_limbs as i64 * 32 + shift + power;
+        for bit in 0..53 {
+            if mantissa & (1 << bit) == 0 { continue; }
+            let position = bit_offset + bit;
+            if position < 0 { continue; }
+            let limb = (position / 32) as usize;
+            if limb < out.limbs.len() {
+                out.limbs[limb] |= 1 << (position % 32);
+            }
+        }
+        return out;
+    }
+
+    /// Parses a decimal string such as "-0.75", "1.5e-20" or "3"
+    pub fn parse(text: &str, frac_limbs: usize) -> Option<BigFloat> {
+        let text = text.trim();
+        let (negative, text) = match text.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, text.strip_prefix('+').unwrap_or(text)),
+        };
+
+        let (mantissa, exponent) = match text.find(['e', 'E']) {
+            Some(index) => (&text[..index], text[index + 1..].parse::<i64>().ok()?),
+            None => (text, 0),
+        };
+        let (int_part, frac_part) = match mantissa.find('.') {
+            Some(index) => (&mantissa[..index], &mantissa[index + 1..]),
+            None => (mantissa, ""),
+        };
+        if int_part.is_empty() && frac_part.is_empty() {
+            return None;
+        }
+
+        // Moves the decimal point by the exponent. Numbers too large for the integer limbs
+        // can't be held & numbers that start past the last fraction limb round to 0
+        let mut digits = Vec::new();
+        for digit in int_part.chars().chain(frac_part.chars()) {
+            digits.push(digit.to_digit(10)?);
+        }
+        let point = (int_part.len() as i64).checked_add(exponent)?;
+        if point > MAX_INT_DIGITS {
+            return None;
+        }
+        if point < -(frac_limbs as i64 * 10) {
+            return Some(BigFloat::zero(frac_limbs));
+        }
+        if point < 0 {
+            digits.splice(0..0, std::iter::repeat_n(0, (-point) as usize));
+        }
+        if point > digits.len() as i64 {
+            digits.resize(point as usize, 0);
+        }
+        let point = point.max(0) as usize;
+
+        // Reads the integer digits from the front
+        let mut out = BigFloat::zero(frac_limbs);
+        for digit in &digits[..point] {
+            out.mul_small(10);
+            out.add_integer(*digit);
+        }
+
+        // Reads the fraction digits from the back
+        let mut fraction = BigFloat::zero(frac_limbs);
+        for digit in digits[point..].iter().rev() {
+            fraction.add_integer(*digit);
+            fraction.div_small(10);
+        }
+
+        out.limbs = out.add_magnitude(&fraction);
+        out.negative = negative && !out.is_zero();
+        return Some(out);
+    }
+
+    /// Converts to the closest f64
+    pub fn to_f64(&self) -> f64 {
+        let mut out = 0.0;
+        for (index, limb) in self.limbs.iter().enumerate().rev() {
+            if *limb == 0 { continue; }
+            out += *limb as f64 * 2f64.powi(32 * (index as i32 - self.frac_limbs as i32));
+        }
+        return if self.negative { -out } else { out };
+    }
+
+    /// Formats as an exact decimal string, which `parse` reads back to the same number
+    pub fn to_decimal(&self) -> String {
+        let mut integer = 0u64;
+        for (index, limb) in self.limbs[self.frac_limbs..].iter().enumerate() {
+            integer |= (*limb as u64) << (32 * index);
+        }
+
+        // Every bit of the fraction ends after one more decimal digit
+        let mut fraction = BigFloat::zero(self.frac_limbs);
+        fraction.limbs[..self.frac_limbs].copy_from_slice(&self.limbs[..self.frac_limbs]);
+        let mut digits = String::new();
+        while !fraction.is_zero() {
+            fraction.mul_small(10);
+            digits.push(char::from(b'0' + fraction.limbs[self.frac_limbs] as u8));
+            fraction.limbs[self.frac_limbs] = 0;
+        }
+
+        let sign = if self.negative { "-" } else { "" };
+        if digits.is_empty() {
+            return format!("{}{}", sign, integer);
+        }
+        return format!("{}{}.{}", sign, integer, digits);
+    }
+
+    pub fn is_negative(&self) -> bool {
+        return self.negative;
+    }
+
+    /// Gets the number with its sign flipped
+    pub fn neg(&self) -> BigFloat {
+        let mut out = self.clone();
+        out.negative = !out.negative && !out.is_zero();
+        return out;
+    }
+
+    fn is_zero(&self) -> bool {
+        return self.limbs.iter().all(|limb| *limb == 0);
+    }
+
+    fn mul_small(&mut self, factor: u32) {
+        let mut carry = 0u64;
+        for limb in self.limbs.iter_mut() {
+            let value = *limb as u64 * factor as u64 + carry;
+            *limb = value as u32;
+            carry = value >> 32;
+        }
+    }
+
+    /// Adds a whole number to the value
+    fn add_integer(&mut self, value: u32) {
+        let mut carry = value as u64;
+        for limb in self.limbs.iter_mut().skip(self.frac_limbs) {
+            if carry == 0 { break; }
+            let sum = *limb as u64 + carry;
+            *limb = sum as u32;
+            carry = sum >> 32;
+        }
+    }
+
+    fn div_small(&mut self, divisor: u32) {
+        let mut remainder = 0u64;
+        for limb in self.limbs.iter_mut().rev() {
+            let value = (remainder << 32) | *limb as u64;
+            *limb = (value / divisor as u64) as u32;
+            remainder = value % divisor as u64;
+        }
+    }
+
+    /// Compares the magnitudes of two numbers
+    fn cmp_magnitude(&self, other: &BigFloat) -> Ordering {
+        for (a, b) in self.limbs.iter().rev().zip(other.limbs.iter().rev()) {
+            match a.cmp(b) {
+                Ordering::Equal => continue,
+                ordering => return ordering,
+            }
+        }
+        return Ordering::Equal;
+    }
+
+    /// Adds magnitudes, ignoring the signs
+    fn add_magnitude(&self, other: &BigFloat) -> Vec<u32> {
+        let mut carry = 0u64;
+        return self.limbs
+            .iter()
+            .zip(other.limbs.iter())
+            .map(|(a, b)| {
+                let sum = *a as u64 + *b as u64 + carry;
+                carry = sum >> 32;
+                sum as u32
+            })
+            .collect();
+    }
+
+    /// Subtracts magnitudes, ignoring the signs (`self` has to be the larger)
+    fn sub_magnitude(&self, other: &BigFloat) -> Vec<u32> {
+        let mut borrow = 0i64;
+        return self.limbs
+            .iter()
+            .zip(other.limbs.iter())
+            .map(|(a, b)| {
+                let mut difference = *a as i64 - *b as i64 - borrow;
+                borrow = 0;
+                if difference < 0 {
+                    difference += 1 << 32;
+                    borrow = 1;
+                }
+                difference as u32
+            })
+            .collect();
+    }
+
+    /// Adds two numbers where `other_negative` overrides the sign of `other`
+    fn signed_add(&self, other: &BigFloat, other_negative: bool) -> BigFloat {
+        let (negative, limbs) = if self.negative == other_negative {
+            (self.negative, self.add_magnitude(other))
+        }
+        else if self.cmp_magnitude(other) != Ordering::Less {
+            (self.negative, self.sub_magnitude(other))
+        }
+        else {
+            (other_negative, other.sub_magnitude(self))
+        };
+
+        let mut out = BigFloat { negative, limbs, frac_limbs: self.frac_limbs };
+        out.negative = out.negative && !out.is_zero();
+        return out;
+    }
+}
+
+impl Add for &BigFloat {
+    type Output = BigFloat;
+
+    fn add(self, other: &BigFloat) -> BigFloat {
+        return self.signed_add(other, other.negative);
+    }
+}
+
+impl Sub for &BigFloat {
+    type Output = BigFloat;
+
+    fn sub(self, other: &BigFloat) -> BigFloat {
+        return self.signed_add(other, !other.negative);
+    }
+}
+
+impl Mul for &BigFloat {
+    type Output = BigFloat;
+
+    fn mul(self, other: &BigFloat) -> BigFloat {
+        let length = self.limbs.len();
+        let mut product = vec![0u64; 2 * length];
+
+        // Schoolbook multiplication, every limb of the product stays below 2^32
+        for (i, a) in self.limbs.iter().enumerate() {
+            if *a == 0 { continue; }
+            let mut carry = 0u128;
+            for (j, b) in other.limbs.iter().enumerate() {
+                let value = product[i + j] as u128 + *a as u128 * *b as u128 + carry;
+                product[i + j] = (value & 0xffff_ffff) as u64;
+                carry = value >> 32;
+            }
+            product[i + length] = carry as u64;
+        }
+
+        // Drops the extra fraction limbs the product has
+        let limbs = product[self.frac_limbs..self.frac_limbs + length]
+            .iter()
+            .map(|limb| *limb as u32)
+            .collect();
+
+        let mut out = BigFloat {
+            negative: self.negative != other.negative,
+            limbs,
+            frac_limbs: self.frac_limbs,
+        };
+        out.negative = out.negative && !out.is_zero();
+        return out;
+    }
+}
+
+/// Complex number made of arbitrary precision parts
+#[derive(Debug, Clone)]
+pub struct BigComplex {
+    pub real: BigFloat,
+    pub imaginary: BigFloat,
+}
+
+impl BigComplex {
+    /// Converts a complex number exactly
+    pub fn from_complex(value: Complex, frac_limbs: usize) -> BigComplex {
+        return BigComplex {
+            real: BigFloat::from_f64(value.real, frac_limbs),
+            imaginary: BigFloat::from_f64(value.imaginary, frac_limbs),
+        };
+    }
+
+    /// Converts to the closest complex number
+    pub fn to_complex(&self) -> Complex {
+        return Complex {
+            real: self.real.to_f64(),
+            imaginary: self.imaginary.to_f64(),
+        };
+    }
+
+    /// Gets the square of the number
+    pub fn square(&self) -> BigComplex {
+        let real = &(&self.real * &self.real) - &(&self.imaginary * &self.imaginary);
+        let cross = &self.real * &self.imaginary;
+        return BigComplex { real, imaginary: &cross + &cross };
+    }
+}
+
+impl Add for &BigComplex {
+    type Output = BigComplex;
+
+    fn add(self, other: &BigComplex) -> BigComplex {
+        return BigComplex {
+            real: &self.real + &other.real,
+            imaginary: &self.imaginary + &other.imaginary,
+        };
+    }
+}
+
+impl Sub for &BigComplex {
+    type Output = BigComplex;
+
+    fn sub(self, other: &BigComplex) -> BigComplex {
+        return BigComplex {
+            real: &self.real - &other.real,
+            imaginary: &self.imaginary - &other.imaginary,
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decimal_round_trip() {
+        for text in ["0", "3", "-0.75", "1.5", "-1234.0625", "0.1", "1e-30", "-2.5e+3", "0.000123456789012345678901234567890123"] {
+            let value = BigFloat::parse(text, 4).unwrap();
+            let decimal = value.to_decimal();
+            assert_eq!(BigFloat::parse(&decimal, 4).unwrap().to_decimal(), decimal, "{}", text);
+
+            // Good to the f64 or to the 128 bits of fraction, whichever is coarser
+            let expected = text.parse::<f64>().unwrap();
+            assert!((value.to_f64() - expected).abs() <= 1e-15 * expected.abs() + 2f64.powi(-128), "{}", text);
+        }
+
+        // Numbers with a short binary fraction come back as they were written
+        assert_eq!(BigFloat::parse("-0.75", 2).unwrap().to_decimal(), "-0.75");
+        assert_eq!(BigFloat::parse("+12", 2).unwrap().to_decimal(), "12");
+        assert_eq!(BigFloat::parse("-0", 2).unwrap().to_decimal(), "0");
+    }
+
+    #[test]
+    fn parses_extreme_exponents() {
+        assert_eq!(BigFloat::parse("9999999999999999999", 2).unwrap().to_f64(), 1e19);
+        assert!(BigFloat::parse("1e19", 2).is_none());
+        assert!(BigFloat::parse("1e9223372036854775807", 2).is_none());
+
+        // Without holding a digit for every power of ten
+        assert!(BigFloat::parse("1e-1000000000000", 2).unwrap().is_zero());
+        assert!(BigFloat::parse("-1e-9223372036854775808", 2).unwrap().is_zero());
+        assert_eq!(BigFloat::parse("1.5e-30", 4).unwrap().to_f64(), BigFloat::parse("0.0000000000000000000000000000015", 4).unwrap().to_f64());
+    }
+
+    #[test]
+    fn keeps_digits_past_f64() {
+        let a = BigFloat::parse("0.1000000000000000000000000001", 4).unwrap();
+        let b = BigFloat::parse("0.1", 4).unwrap();
+        assert_eq!(a.to_f64(), b.to_f64());
+        assert!(a.to_decimal() != b.to_decimal());
+    }
+
+    #[test]
+    fn invalid_numbers() {
+        for text in ["", "-", ".", "abc", "1e", "1.2.3", "1e5x", "--1"] {
+            assert!(BigFloat::parse(text, 2).is_none(), "{}", text);
+        }
+    }
+}
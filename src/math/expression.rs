@@ -0,0 +1,601 @@
+/*
+# Purpose
+User defined formulas, such as "z^3 + c - conj(z)*0.2". The text is parsed
+once and compiled into a list of stack operations, which is all that runs
+for every step of every pixel.
+*/
+
+use crate::error::KyrosError;
+use crate::structs::Complex;
+
+use std::f64::consts::{E, PI};
+
+/// Deepest the evaluation stack can get
+const MAX_STACK: usize = 64;
+
+/// Deepest parentheses, calls & signs can be nested, so the parser can't run out of stack
+const MAX_DEPTH: usize = 256;
+
+/// Most tokens an expression can have, which bounds the depth of long chains such as "z+z+...+z"
+const MAX_TOKENS: usize = 4096;
+
+/// Functions that can be called from an expression
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Function {
+    Abs, Fabs, Conj, Re, Im, Arg, Exp, Ln, Sqrt, Sin, Cos, Tan, Sinh, Cosh, Tanh,
+}
+
+/// Sets Bootleg hashmap for functions
+///   FUNCTIONS.0 == Name in expressions
+///   FUNCTIONS.1 == Function Value
+///   FUNCTIONS.2 == Documentation Value
+const FUNCTIONS: [(&str, Function, &str);16] = [
+    ("abs"  , Function::Abs  , "Absolute value |z|"),
+    ("fabs" , Function::Fabs , "Absolute value of both parts |re| + |im|i"),
+    ("conj" , Function::Conj , "Complex conjugate"),
+    ("re"   , Function::Re   , "Real part"),
+    ("im"   , Function::Im   , "Imaginary part"),
+    ("arg"  , Function::Arg  , "Angle to the positive real axis"),
+    ("exp"  , Function::Exp  , "Exponential"),
+    ("ln"   , Function::Ln   , "Natural logarithm"),
+    ("log"  , Function::Ln   , "Natural logarithm"),
+    ("sqrt" , Function::Sqrt , "Square root"),
+    ("sin"  , Function::Sin  , "Sine"),
+    ("cos"  , Function::Cos  , "Cosine"),
+    ("tan"  , Function::Tan  , "Tangent"),
+    ("sinh" , Function::Sinh , "Hyperbolic sine"),
+    ("cosh" , Function::Cosh , "Hyperbolic cosine"),
+    ("tanh" , Function::Tanh , "Hyperbolic tangent"),
+];
+
+impl Function {
+    fn apply(self, value: Complex) -> Complex {
+        let real = |real: f64| Complex { real, imaginary: 0.0 };
+        return match self {
+            Function::Abs  => real(value.abs()),
+            Function::Fabs => Complex { real: value.real.abs(), imaginary: value.imaginary.abs() },
+            Function::Conj => value.conj(),
+            Function::Re   => real(value.real),
+            Function::Im   => real(value.imaginary),
+            Function::Arg  => real(value.arg()),
+            Function::Exp  => value.exp(),
+            Function::Ln   => value.ln(),
+            Function::Sqrt => value.sqrt(),
+            Function::Sin  => value.sin(),
+            Function::Cos  => value.cos(),
+            Function::Tan  => value.sin() / value.cos(),
+            Function::Sinh => value.sinh(),
+            Function::Cosh => value.cosh(),
+            Function::Tanh => value.sinh() / value.cosh(),
+        };
+    }
+}
+
+/// Error from parsing an expression, `column` starts at 1
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    pub column: usize,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(f64),
+    Imaginary(f64),
+    Name(String),
+    Symbol(char),
+    End,
+}
+
+/// Splits the text into tokens, each with the column it starts at
+fn tokenize(text: &str) -> Result<Vec<(Token, usize)>, ParseError> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut tokens = Vec::new();
+    let mut index = 0;
+
+    while index < chars.len() {
+        let character = chars[index];
+        let column = index + 1;
+
+        if character.is_whitespace() {
+            index += 1;
+        }
+        else if character.is_ascii_digit() || character == '.' {
+            let start = index;
+            while index < chars.len() && (chars[index].is_ascii_digit() || chars[index] == '.') {
+                index += 1;
+            }
+            // Exponent, such as the "e-3" of "1.5e-3"
+            if index < chars.len() && (chars[index] == 'e' || chars[index] == 'E') {
+                let mut end = index + 1;
+                if end < chars.len() && (chars[end] == '+' || chars[end] == '-') { end += 1; }
+                if end < chars.len() && chars[end].is_ascii_digit() {
+                    index = end;
+                    while index < chars.len() && chars[index].is_ascii_digit() { index += 1; }
+                }
+            }
+            let number_text: String = chars[start..index].iter().collect();
+            let number = number_text.parse::<f64>().map_err(|_| ParseError {
+                column,
+                message: format!("'{}' is not a number", number_text),
+            })?;
+
+            // A trailing 'i' makes the number imaginary
+            if index < chars.len() && chars[index] == 'i' && !chars.get(index + 1).is_some_and(|c| c.is_alphanumeric()) {
+                index += 1;
+                tokens.push((Token::Imaginary(number), column));
+            }
+            else {
+                tokens.push((Token::Number(number), column));
+            }
+        }
+        else if character.is_alphabetic() || character == '_' {
+            let start = index;
+            while index < chars.len() && (chars[index].is_alphanumeric() || chars[index] == '_') {
+                index += 1;
+            }
+            tokens.push((Token::Name(chars[start..index].iter().collect()), column));
+        }
+        else if "+-*/^()".contains(character) {
+            index += 1;
+            tokens.push((Token::Symbol(character), column));
+        }
+        else {
+            return Err(ParseError { column, message: format!("unexpected character '{}'", character) });
+        }
+    }
+    tokens.push((Token::End, chars.len() + 1));
+    return Ok(tokens);
+}
+
+/// Parsed expression tree, the leaves keep the column they were read from
+#[derive(Debug, Clone)]
+enum Node {
+    Z(usize),
+    C(usize),
+    Constant(Complex, usize),
+    Negate(Box<Node>),
+    Call(Function, Box<Node>),
+    Binary(char, Box<Node>, Box<Node>),
+}
+
+/// Recursive descent parser over the tokens
+struct Parser {
+    tokens: Vec<(Token, usize)>,
+    position: usize,
+    depth: usize, // Amount of unary rules currently being parsed, one for every level of nesting
+}
+
+impl Parser {
+    fn peek(&self) -> &Token {
+        return &self.tokens[self.position].0;
+    }
+
+    fn column(&self) -> usize {
+        return self.tokens[self.position].1;
+    }
+
+    fn error<T>(&self, message: &str) -> Result<T, ParseError> {
+        let found = match self.peek() {
+            Token::End => "the end".to_string(),
+            Token::Number(number) | Token::Imaginary(number) => format!("'{}'", number),
+            Token::Name(name) => format!("'{}'", name),
+            Token::Symbol(symbol) => format!("'{}'", symbol),
+        };
+        return Err(ParseError { column: self.column(), message: format!("expected {}, found {}", message, found) });
+    }
+
+    fn eat(&mut self, symbol: char) -> bool {
+        if *self.peek() == Token::Symbol(symbol) {
+            self.position += 1;
+            return true;
+        }
+        return false;
+    }
+
+    /// expression := term (('+' | '-') term)*
+    fn expression(&mut self) -> Result<Node, ParseError> {
+        let mut node = self.term()?;
+        loop {
+            let operator = match self.peek() {
+                Token::Symbol(symbol) if *symbol == '+' || *symbol == '-' => *symbol,
+                _ => return Ok(node),
+            };
+            self.position += 1;
+            node = Node::Binary(operator, Box::new(node), Box::new(self.term()?));
+        }
+    }
+
+    /// term := unary (('*' | '/') unary)*
+    fn term(&mut self) -> Result<Node, ParseError> {
+        let mut node = self.unary()?;
+        loop {
+            let operator = match self.peek() {
+                Token::Symbol(symbol) if *symbol == '*' || *symbol == '/' => *symbol,
+                _ => return Ok(node),
+            };
+            self.position += 1;
+            node = Node::Binary(operator, Box::new(node), Box::new(self.unary()?));
+        }
+    }
+
+    /// unary := ('-' | '+') unary | power
+    fn unary(&mut self) -> Result<Node, ParseError> {
+        if self.depth >= MAX_DEPTH {
+            return Err(ParseError {
+                column: self.column(),
+                message: format!("expression is nested more than {} levels deep", MAX_DEPTH),
+            });
+        }
+        self.depth += 1;
+        let node = self.signed();
+        self.depth -= 1;
+        return node;
+    }
+
+    fn signed(&mut self) -> Result<Node, ParseError> {
+        if self.eat('-') {
+            return Ok(Node::Negate(Box::new(self.unary()?)));
+        }
+        if self.eat('+') {
+            return self.unary();
+        }
+        return self.power();
+    }
+
+    /// power := atom ('^' unary)?, so "z^2^3" is z^(2^3) and "-z^2" is -(z^2)
+    fn power(&mut self) -> Result<Node, ParseError> {
+        let base = self.atom()?;
+        if self.eat('^') {
+            return Ok(Node::Binary('^', Box::new(base), Box::new(self.unary()?)));
+        }
+        return Ok(base);
+    }
+
+    /// atom := number | name | name '(' expression ')' | '(' expression ')'
+    fn atom(&mut self) -> Result<Node, ParseError> {
+        let column = self.column();
+        match self.peek().clone() {
+            Token::Number(number) => {
+                self.position += 1;
+                return Ok(Node::Constant(Complex { real: number, imaginary: 0.0 }, column));
+            },
+            Token::Imaginary(number) => {
+                self.position += 1;
+                return Ok(Node::Constant(Complex { real: 0.0, imaginary: number }, column));
+            },
+            Token::Symbol('(') => {
+                self.position += 1;
+                let node = self.expression()?;
+                if !self.eat(')') {
+                    return self.error("')'");
+                }
+                return Ok(node);
+            },
+            Token::Name(name) => {
+                self.position += 1;
+                let constant = |real, imaginary| Ok(Node::Constant(Complex { real, imaginary }, column));
+                match name.as_str() {
+                    "z"  => return Ok(Node::Z(column)),
+                    "c"  => return Ok(Node::C(column)),
+                    "i"  => return constant(0.0, 1.0),
+                    "pi" => return constant(PI, 0.0),
+                    "e"  => return constant(E, 0.0),
+                    _ => {},
+                }
+
+                let function = match FUNCTIONS.iter().find(|(key, _, _)| *key == name) {
+                    Some((_, function, _)) => *function,
+                    None => {
+                        let names: Vec<&str> = FUNCTIONS.iter().map(|v| v.0).collect();
+                        return Err(ParseError {
+                            column,
+                            message: format!("unknown name '{}' (use z, c, i, pi, e or one of {})", name, names.join(", ")),
+                        });
+                    },
+                };
+                if !self.eat('(') {
+                    return self.error(&format!("'(' after '{}'", name));
+                }
+                let argument = self.expression()?;
+                if !self.eat(')') {
+                    return self.error("')'");
+                }
+                return Ok(Node::Call(function, Box::new(argument)));
+            },
+            _ => return self.error("a number, name or '('"),
+        }
+    }
+}
+
+/// Single step of a compiled expression
+#[derive(Debug, Clone, Copy)]
+enum Op {
+    Z,
+    C,
+    Constant(Complex),
+    Negate,
+    Call(Function),
+    Add,
+    Sub,
+    Mul,
+    Div,
+    PowInt(i32),
+    Pow,
+}
+
+/// Compiled expression, ready to be run on every pixel
+#[derive(Debug, Clone)]
+pub struct Expression {
+    ops: Vec<Op>,
+    degree: f64,
+}
+
+/// Gets the value of a node if it doesn't depend on z or c
+fn constant_value(node: &Node) -> Option<Complex> {
+    return match node {
+        Node::Z(_) | Node::C(_) => None,
+        Node::Constant(value, _) => Some(*value),
+        Node::Negate(inner) => Some(-constant_value(inner)?),
+        Node::Call(function, inner) => Some(function.apply(constant_value(inner)?)),
+        Node::Binary(operator, left, right) => {
+            Some(binary(*operator, constant_value(left)?, constant_value(right)?))
+        },
+    };
+}
+
+fn binary(operator: char, left: Complex, right: Complex) -> Complex {
+    return match operator {
+        '+' => left + right,
+        '-' => left - right,
+        '*' => left * right,
+        '/' => left / right,
+        _   => left.powc(right),
+    };
+}
+
+/// Gets the power |z| grows with for large z, infinite for anything faster than a polynomial
+fn degree(node: &Node) -> f64 {
+    return match node {
+        Node::Z(_) => 1.0,
+        Node::C(_) | Node::Constant(_, _) => 0.0,
+        Node::Negate(inner) => degree(inner),
+        Node::Call(function, inner) => match function {
+            Function::Abs | Function::Fabs | Function::Conj | Function::Re | Function::Im => degree(inner),
+            Function::Sqrt => 0.5 * degree(inner),
+            Function::Arg | Function::Ln => 0.0,
+            _ if degree(inner) == 0.0 => 0.0,
+            _ => f64::INFINITY,
+        },
+        Node::Binary(operator, left, right) => match operator {
+            '+' | '-' => degree(left).max(degree(right)),
+            '*' => degree(left) + degree(right),
+            '/' => degree(left) - degree(right),
+            _ => match constant_value(right) {
+                Some(power) if power.imaginary == 0.0 => degree(left) * power.real,
+                _ if degree(left) == 0.0 && degree(right) == 0.0 => 0.0,
+                _ => f64::INFINITY,
+            },
+        },
+    };
+}
+
+/// Gets the column of the first leaf of a node
+fn column(node: &Node) -> usize {
+    return match node {
+        Node::Z(column) | Node::C(column) | Node::Constant(_, column) => *column,
+        Node::Negate(inner) | Node::Call(_, inner) => column(inner),
+        Node::Binary(_, left, _) => column(left),
+    };
+}
+
+/// Turns the tree into stack operations, each with the column of the node it came from,
+/// folding everything that is constant
+fn compile(node: &Node, ops: &mut Vec<(Op, usize)>) {
+    if let Some(value) = constant_value(node) {
+        ops.push((Op::Constant(value), column(node)));
+        return;
+    }
+    match node {
+        Node::Z(column) => ops.push((Op::Z, *column)),
+        Node::C(column) => ops.push((Op::C, *column)),
+        Node::Constant(value, column) => ops.push((Op::Constant(*value), *column)),
+        Node::Negate(inner) => {
+            compile(inner, ops);
+            ops.push((Op::Negate, column(node)));
+        },
+        Node::Call(function, inner) => {
+            compile(inner, ops);
+            ops.push((Op::Call(*function), column(node)));
+        },
+        Node::Binary(operator, left, right) => {
+            compile(left, ops);
+
+            // Whole powers get repeated squaring, which is exact & much faster
+            if *operator == '^' {
+                if let Some(power) = constant_value(right) {
+                    if power.imaginary == 0.0 && power.real.fract() == 0.0 && power.real.abs() <= 64.0 {
+                        ops.push((Op::PowInt(power.real as i32), column(node)));
+                        return;
+                    }
+                }
+            }
+            compile(right, ops);
+            ops.push((match operator {
+                '+' => Op::Add,
+                '-' => Op::Sub,
+                '*' => Op::Mul,
+                '/' => Op::Div,
+                _   => Op::Pow,
+            }, column(node)));
+        },
+    }
+}
+
+impl Expression {
+
+    /// Parses & compiles an expression
+    pub fn parse(text: &str) -> Result<Expression, ParseError> {
+        let tokens = tokenize(text)?;
+        if tokens.len() > MAX_TOKENS {
+            return Err(ParseError {
+                column: tokens[MAX_TOKENS].1,
+                message: format!("expression is longer than {} tokens", MAX_TOKENS),
+            });
+        }
+        let mut parser = Parser { tokens, position: 0, depth: 0 };
+        let node = parser.expression()?;
+        if *parser.peek() != Token::End {
+            return parser.error("an operator");
+        }
+
+        let mut ops = Vec::new();
+        compile(&node, &mut ops);
+
+        // Checks the stack never gets deeper than the evaluator allows
+        let mut depth: usize = 0;
+        for (op, column) in ops.iter() {
+            match op {
+                Op::Z | Op::C | Op::Constant(_) => depth += 1,
+                Op::Add | Op::Sub | Op::Mul | Op::Div | Op::Pow => depth -= 1,
+                _ => {},
+            }
+            if depth > MAX_STACK {
+                return Err(ParseError { column: *column, message: "expression is nested too deeply".to_string() });
+            }
+        }
+
+        let ops = ops.into_iter().map(|(op, _)| op).collect();
+        return Ok(Expression { ops, degree: degree(&node) });
+    }
+
+    /// Gets the power |z| grows with once the orbit escapes (2 when it isn't a polynomial in z)
+    pub fn degree(&self) -> f64 {
+        if self.degree.is_finite() && self.degree > 1.0 {
+            return self.degree;
+        }
+        return 2.0;
+    }
+
+    /// Runs the expression for one step of an orbit
+    pub fn eval(&self, c: Complex, z: Complex) -> Complex {
+        let mut stack = [Complex { real: 0.0, imaginary: 0.0 }; MAX_STACK];
+        let mut top = 0;
+
+        for op in self.ops.iter() {
+            match *op {
+                Op::Z => { stack[top] = z; top += 1; },
+                Op::C => { stack[top] = c; top += 1; },
+                Op::Constant(value) => { stack[top] = value; top += 1; },
+                Op::Negate => stack[top - 1] = -stack[top - 1],
+                Op::Call(function) => stack[top - 1] = function.apply(stack[top - 1]),
+                Op::PowInt(power) => stack[top - 1] = stack[top - 1].powi(power),
+                Op::Add => { top -= 1; stack[top - 1] = stack[top - 1] + stack[top]; },
+                Op::Sub => { top -= 1; stack[top - 1] = stack[top - 1] - stack[top]; },
+                Op::Mul => { top -= 1; stack[top - 1] = stack[top - 1] * stack[top]; },
+                Op::Div => { top -= 1; stack[top - 1] = stack[top - 1] / stack[top]; },
+                Op::Pow => { top -= 1; stack[top - 1] = stack[top - 1].powc(stack[top]); },
+            }
+        }
+        return stack[0];
+    }
+}
+
+/// Gets the list of functions expressions can call, for error messages
+pub fn function_string() -> String {
+    return FUNCTIONS
+        .iter()
+        .map(|v| format!("  {}\t{}", v.0, v.2))
+        .collect::<Vec<String>>()
+        .join("\n");
+}
+
+/// Function for compiling the formula expression from config
+pub fn get_expression(text: &str) -> Result<Expression, KyrosError> {
+    return Expression::parse(text).map_err(|error| KyrosError::Expression {
+        text: text.to_string(),
+        error,
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn deep_nesting_is_an_error() {
+        let text = format!("{}z{}", "(".repeat(20000), ")".repeat(20000));
+        let error = Expression::parse(&text).unwrap_err();
+        assert_eq!(error.column, 4097);
+
+        let text = format!("{}z", "-".repeat(20000));
+        assert!(Expression::parse(&text).is_err());
+
+        let error = Expression::parse(&format!("{}z{}", "(".repeat(300), ")".repeat(300))).unwrap_err();
+        assert_eq!(error.column, MAX_DEPTH + 1);
+        assert!(error.message.contains("nested"));
+    }
+
+    #[test]
+    fn nesting_under_the_limit_parses() {
+        let text = format!("{}z{}", "(".repeat(200), ")".repeat(200));
+        assert!(Expression::parse(&text).is_ok());
+        assert!(Expression::parse(&vec!["z"; 1000].join("+")).is_ok());
+    }
+
+    #[test]
+    fn powers_of_zero() {
+        let zero = Complex { real: 0.0, imaginary: 0.0 };
+        let power = |text: &str, c: Complex| Expression::parse(text).unwrap().eval(c, zero);
+
+        assert_eq!(power("z^c", zero).real, 1.0);
+        assert_eq!(power("z^c", Complex { real: 2.0, imaginary: 0.0 }).real, 0.0);
+        assert!(!power("z^c", Complex { real: -1.0, imaginary: 0.0 }).real.is_finite());
+        assert!(!power("z^c", Complex { real: 0.0, imaginary: 1.0 }).abs().is_finite());
+    }
+
+    fn eval(text: &str, z: f64) -> Complex {
+        let c = Complex { real: 0.0, imaginary: 0.0 };
+        return Expression::parse(text).unwrap().eval(c, Complex { real: z, imaginary: 0.0 });
+    }
+
+    #[test]
+    fn precedence() {
+        assert_eq!(eval("1 + 2 * 3", 0.0).real, 7.0);
+        assert_eq!(eval("(1 + 2) * 3", 0.0).real, 9.0);
+        assert_eq!(eval("8 / 4 / 2", 0.0).real, 1.0);
+        assert_eq!(eval("-z^2", 3.0).real, -9.0);
+        assert!((eval("2^3^2", 0.0).real - 512.0).abs() < 1e-9);
+        assert!((eval("2^-1", 0.0).real - 0.5).abs() < 1e-12);
+        assert_eq!(eval("z - -z", 2.0).real, 4.0);
+
+        let value = eval("2i * i", 0.0);
+        assert_eq!((value.real, value.imaginary), (-2.0, 0.0));
+    }
+
+    #[test]
+    fn errors_point_at_their_column() {
+        let error = |text: &str| Expression::parse(text).unwrap_err();
+
+        assert_eq!(error("z + * c").column, 5);
+        assert_eq!(error("z $ c").column, 3);
+        assert_eq!(error("z + foo(c)").column, 5);
+        assert!(error("z + foo(c)").message.contains("unknown name 'foo'"));
+        assert_eq!(error("(z + c").column, 7);
+        assert!(error("(z + c").message.contains("expected ')'"));
+        assert_eq!(error("z c").column, 3);
+        assert_eq!(error("").column, 1);
+
+        // The 65th z is the one that doesn't fit on the stack
+        let text = format!("{}z{}", "z+(".repeat(64), ")".repeat(64));
+        assert_eq!(error(&text).column, 193);
+        assert!(error(&text).message.contains("nested too deeply"));
+    }
+
+    #[test]
+    fn degree_of_polynomials() {
+        assert_eq!(Expression::parse("z^3 + c").unwrap().degree(), 3.0);
+        assert_eq!(Expression::parse("z*z*z*z - z + c").unwrap().degree(), 4.0);
+        assert_eq!(Expression::parse("sin(z) + c").unwrap().degree(), 2.0);
+    }
+}
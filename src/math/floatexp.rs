@@ -0,0 +1,152 @@
+/*
+  File for the extended range floats used by the deepest zooms
+*/
+
+use std::cmp::Ordering;
+use std::ops::{ Add, Sub, Mul, Neg };
+
+/// Numbers the perturbation deltas can be stored as
+pub trait Real: Copy + PartialOrd + Add<Output = Self> + Sub<Output = Self> + Mul<Output = Self> + Neg<Output = Self> + Send + Sync {
+    fn from_f64(value: f64) -> Self;
+    fn from_floatexp(value: FloatExp) -> Self;
+    fn to_f64(self) -> f64;
+    fn to_floatexp(self) -> FloatExp;
+    fn zero() -> Self { Self::from_f64(0.0) }
+    fn is_negative(self) -> bool { self < Self::zero() }
+}
+
+impl Real for f64 {
+    fn from_f64(value: f64) -> f64 { value }
+    fn from_floatexp(value: FloatExp) -> f64 { value.to_f64() }
+    fn to_f64(self) -> f64 { self }
+    fn to_floatexp(self) -> FloatExp { FloatExp::from_f64(self) }
+}
+
+/// Float with a separate exponent, so values far below f64's range
+/// (such as pixel spacings past 1e-300) don't underflow.
+/// The value is `mantissa * 2^exponent` with the mantissa in [0.5, 1) or 0.
+#[derive(Debug, Clone, Copy)]
+pub struct FloatExp {
+    mantissa: f64,
+    exponent: i64,
+}
+
+impl FloatExp {
+    /// Brings the mantissa back into [0.5, 1)
+    fn normalized(mantissa: f64, exponent: i64) -> FloatExp {
+        if mantissa == 0.0 || !mantissa.is_finite() {
+            return FloatExp { mantissa, exponent: 0 };
+        }
+        let bits = mantissa.to_bits();
+        let raw_exponent = ((bits >> 52) & 0x7ff) as i64;
+        if raw_exponent == 0 {
+            // Subnormal mantissas are scaled up first
+            return FloatExp::normalized(mantissa * 2f64.powi(64), exponent - 64);
+        }
+        let shift = raw_exponent - 1022;
+        let mantissa = f64::from_bits((bits & !(0x7ff << 52)) | (1022 << 52));
+        return FloatExp { mantissa, exponent: exponent + shift };
+    }
+
+    /// Multiplies by a power of 2
+    pub fn scale(self, power: i64) -> FloatExp {
+        return FloatExp::normalized(self.mantissa, self.exponent + power);
+    }
+
+    /// Gets the mantissa & the power of 2 it is multiplied by
+    pub fn parts(self) -> (f64, i64) {
+        return (self.mantissa, self.exponent);
+    }
+}
+
+impl Real for FloatExp {
+    fn from_f64(value: f64) -> FloatExp {
+        return FloatExp::normalized(value, 0);
+    }
+
+    fn from_floatexp(value: FloatExp) -> FloatExp {
+        return value;
+    }
+
+    fn to_f64(self) -> f64 {
+        if self.exponent > 1100 {
+            return self.mantissa * f64::INFINITY;
+        }
+        if self.exponent < -1100 {
+            return 0.0;
+        }
+        // Split in two steps so neither power of 2 leaves the f64 range
+        let half = self.exponent / 2;
+        return self.mantissa * 2f64.powi(half as i32) * 2f64.powi((self.exponent - half) as i32);
+    }
+
+    fn to_floatexp(self) -> FloatExp {
+        return self;
+    }
+
+    fn is_negative(self) -> bool {
+        return self.mantissa < 0.0;
+    }
+}
+
+impl Add for FloatExp {
+    type Output = FloatExp;
+
+    fn add(self, other: FloatExp) -> FloatExp {
+        if self.mantissa == 0.0 { return other; }
+        if other.mantissa == 0.0 { return self; }
+
+        // Lines the smaller value up with the exponent of the larger one
+        let (large, small) = if self.exponent >= other.exponent { (self, other) } else { (other, self) };
+        let difference = large.exponent - small.exponent;
+        if difference > 64 {
+            return large;
+        }
+        let mantissa = large.mantissa + small.mantissa * 2f64.powi(-(difference as i32));
+        return FloatExp::normalized(mantissa, large.exponent);
+    }
+}
+
+impl Sub for FloatExp {
+    type Output = FloatExp;
+
+    fn sub(self, other: FloatExp) -> FloatExp {
+        return self + -other;
+    }
+}
+
+impl Mul for FloatExp {
+    type Output = FloatExp;
+
+    fn mul(self, other: FloatExp) -> FloatExp {
+        return FloatExp::normalized(self.mantissa * other.mantissa, self.exponent + other.exponent);
+    }
+}
+
+impl Neg for FloatExp {
+    type Output = FloatExp;
+
+    fn neg(self) -> FloatExp {
+        return FloatExp { mantissa: -self.mantissa, exponent: self.exponent };
+    }
+}
+
+impl PartialEq for FloatExp {
+    fn eq(&self, other: &FloatExp) -> bool {
+        return self.partial_cmp(other) == Some(Ordering::Equal);
+    }
+}
+
+impl PartialOrd for FloatExp {
+    fn partial_cmp(&self, other: &FloatExp) -> Option<Ordering> {
+        // Signs decide first, then exponents, then mantissas
+        let self_sign = self.mantissa.partial_cmp(&0.0)?;
+        let other_sign = other.mantissa.partial_cmp(&0.0)?;
+        if self_sign != other_sign || self_sign == Ordering::Equal {
+            return Some(self_sign.cmp(&other_sign));
+        }
+        let by_size = self.exponent.cmp(&other.exponent)
+            .then(self.mantissa.abs().partial_cmp(&other.mantissa.abs())?);
+        return Some(if self_sign == Ordering::Less { by_size.reverse() } else { by_size });
+    }
+}
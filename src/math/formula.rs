@@ -1,10 +1,7 @@
 #![allow(non_snake_case)]
 
 use super::super::structs;
-use super::super::Args;
-
-use clap::error::ErrorKind;
-use clap::CommandFactory;
+use crate::error::KyrosError;
 
 /*
 # Purpose
@@ -37,7 +34,7 @@ fn ABR(c: structs::Complex, z: structs::Complex) -> structs::Complex {
 fn BS(c: structs::Complex, mut z: structs::Complex) -> structs::Complex {
     z = z * z;
     if z.imaginary > 0.0 {
-        z.imaginary = z.imaginary * -1.0;
+        z.imaginary = -z.imaginary;
     }
     return z + c;
 }
@@ -46,37 +43,145 @@ fn SYM(c: structs::Complex, z: structs::Complex) -> structs::Complex {
     return z * z + c - z;
 }
 
+/// Coefficients of z^3 - 1 (from the highest power down), the polynomial of Newton style formulas when none is set
+const CUBIC: [f64;4] = [1.0, 0.0, 0.0, -1.0];
+
+/// Coefficients of z^3, the polynomial of MULTIBROT when none is set
+const CUBE: [f64;4] = [1.0, 0.0, 0.0, 0.0];
+
+/// Gets the value & derivative of a polynomial at z, with its coefficients from the highest power down
+fn polynomial(coefficients: &[f64], z: structs::Complex) -> (structs::Complex, structs::Complex) {
+    let zero = structs::Complex { real: 0.0, imaginary: 0.0 };
+    let (mut value, mut derivative) = (zero, zero);
+    for coefficient in coefficients.iter() {
+        derivative = derivative * z + value;
+        value = value * z + structs::Complex { real: *coefficient, imaginary: 0.0 };
+    }
+    return (value, derivative);
+}
+
+/// One step of Newton's method towards a root of the polynomial
+fn newton_step(coefficients: &[f64], z: structs::Complex) -> structs::Complex {
+    let (value, derivative) = polynomial(coefficients, z);
+    return z - value / derivative;
+}
+
+fn NEWTON_polynomial(coefficients: &[f64], _: structs::Complex, z: structs::Complex) -> structs::Complex {
+    return newton_step(coefficients, z);
+}
+
+/// Nova starts at z = 1, the critical point for z^3 - 1 (a root of it). A polynomial from the config
+/// keeps that start, even though 1 is only a critical point of the step when it is a root of the
+/// polynomial or of its second derivative, so the image isn't the exact parameter plane then.
+fn NOVA_polynomial(coefficients: &[f64], c: structs::Complex, z: structs::Complex) -> structs::Complex {
+    return newton_step(coefficients, z) + c;
+}
+
+fn MULTIBROT_polynomial(coefficients: &[f64], c: structs::Complex, z: structs::Complex) -> structs::Complex {
+    return polynomial(coefficients, z).0 + c;
+}
+
+fn NEWTON(c: structs::Complex, z: structs::Complex) -> structs::Complex {
+    return NEWTON_polynomial(&CUBIC, c, z);
+}
+
+fn NOVA(c: structs::Complex, z: structs::Complex) -> structs::Complex {
+    return NOVA_polynomial(&CUBIC, c, z);
+}
+
+fn MULTIBROT(c: structs::Complex, z: structs::Complex) -> structs::Complex {
+    return MULTIBROT_polynomial(&CUBE, c, z);
+}
+
+fn MAGNET1(c: structs::Complex, z: structs::Complex) -> structs::Complex {
+    let one = structs::Complex { real: 1.0, imaginary: 0.0 };
+    let two = structs::Complex { real: 2.0, imaginary: 0.0 };
+    let ratio = (z * z + c - one) / (two * z + c - two);
+    return ratio * ratio;
+}
+
+fn MAGNET2(c: structs::Complex, z: structs::Complex) -> structs::Complex {
+    let one = structs::Complex { real: 1.0, imaginary: 0.0 };
+    let two = structs::Complex { real: 2.0, imaginary: 0.0 };
+    let three = structs::Complex { real: 3.0, imaginary: 0.0 };
+    let c1 = c - one;
+    let c2 = c - two;
+    let ratio = (z * z * z + three * c1 * z + c1 * c2) / (three * z * z + three * c2 * z + c1 * c2 + one);
+    return ratio * ratio;
+}
+
+fn SD_derivative(_: &[f64], _: structs::Complex, z: structs::Complex) -> (structs::Complex, structs::Complex) {
+    return (structs::Complex { real: 2.0 * z.real, imaginary: 2.0 * z.imaginary }, ONE_C);
+}
+
+fn SYM_derivative(_: &[f64], _: structs::Complex, z: structs::Complex) -> (structs::Complex, structs::Complex) {
+    return (structs::Complex { real: 2.0 * z.real - 1.0, imaginary: 2.0 * z.imaginary }, ONE_C);
+}
+
+fn MULTIBROT_derivative(coefficients: &[f64], _: structs::Complex, z: structs::Complex) -> (structs::Complex, structs::Complex) {
+    let coefficients = if coefficients.is_empty() { &CUBE[..] } else { coefficients };
+    return (polynomial(coefficients, z).1, ONE_C);
+}
+
+/// Type of every generator function, `(c, z) -> z`
+pub type FormulaFn = dyn Fn(structs::Complex, structs::Complex) -> structs::Complex + Sync;
+
+/// Type of the generator function of Newton style formulas, `(coefficients, c, z) -> z`
+pub type PolynomialFn = dyn Fn(&[f64], structs::Complex, structs::Complex) -> structs::Complex + Sync;
+
+/// Type of the derivatives of a formula, `(coefficients, c, z) -> (df/dz, df/dc)`.
+/// The coefficients are the polynomial of the config, empty when none is set.
+pub type FormulaDerivativeFn = dyn Fn(&[f64], structs::Complex, structs::Complex) -> (structs::Complex, structs::Complex) + Sync;
+
+/// Starting z of the formulas that don't start at the pixel (outside of julia sets)
+const ZERO: Option<structs::Complex> = Some(structs::Complex { real: 0.0, imaginary: 0.0 });
+const ONE: Option<structs::Complex> = Some(structs::Complex { real: 1.0, imaginary: 0.0 });
+
+/// Derivative by c of the formulas that add c
+const ONE_C: structs::Complex = structs::Complex { real: 1.0, imaginary: 0.0 };
+
+/// Entry of the FORMULAS table
+pub struct Formula {
+    pub name: &'static str,
+    pub function: &'static FormulaFn,
+    pub degree: f64, // Power |z| grows with once the orbit escapes, used by smooth coloring
+    pub bailout: f64, // Escape radius used when none is set
+    pub escape_norm: &'static str, // Escape test used when none is set, from ESCAPE_NORMS
+    pub start: Option<structs::Complex>, // Starting z (the critical point) in place of the pixel, outside of julia sets, kept with any polynomial
+    pub polynomial: Option<&'static PolynomialFn>, // Version of the function using the polynomial of the config
+    pub derivative: Option<&'static FormulaDerivativeFn>, // Analytic derivatives, used by distance estimation (taken numerically without them)
+    pub description: &'static str,
+}
+
 /// Sets Bootleg hashmap for formulas
-///   FORMULAS.0 == Key Value
-///   FORMULAS.1 == Function Value
-///   FORMULAS.2 == Documentation Value
-const FORMULAS: [(&str, &dyn Fn(structs::Complex, structs::Complex) -> structs::Complex, &str);5] = [
-    ("SD"  , &SD  , "Standard z = z^2 + c"),
-    ("R"   , &R   , "Custom Rabbit Generator"),
-    ("ABR" , &ABR , "Absolute Value Rabbit Generator"),
-    ("BS"  , &BS  , "Burning Ship Generator"),
-    ("SYM" , &SYM , "A Symetrical Mandelbrot Like Generation"),
+pub const FORMULAS: [Formula;10] = [
+    Formula { name: "SD"        , function: &SD        , degree: 2.0, bailout: 2.0, escape_norm: "EUCLIDEAN"         , start: None, polynomial: None                        , derivative: Some(&SD_derivative)        , description: "Standard z = z^2 + c" },
+    Formula { name: "R"         , function: &R         , degree: 2.0, bailout: 2.0, escape_norm: "EUCLIDEAN"         , start: None, polynomial: None                        , derivative: None                        , description: "Custom Rabbit Generator" },
+    Formula { name: "ABR"       , function: &ABR       , degree: 2.0, bailout: 2.0, escape_norm: "EUCLIDEAN"         , start: None, polynomial: None                        , derivative: None                        , description: "Absolute Value Rabbit Generator" },
+    Formula { name: "BS"        , function: &BS        , degree: 2.0, bailout: 2.0, escape_norm: "EUCLIDEAN"         , start: None, polynomial: None                        , derivative: None                        , description: "Burning Ship Generator" },
+    Formula { name: "SYM"       , function: &SYM       , degree: 2.0, bailout: 2.0, escape_norm: "EUCLIDEAN"         , start: None, polynomial: None                        , derivative: Some(&SYM_derivative)       , description: "A Symetrical Mandelbrot Like Generation" },
+    Formula { name: "NEWTON"    , function: &NEWTON    , degree: 2.0, bailout: 1e6, escape_norm: "STEP"              , start: None, polynomial: Some(&NEWTON_polynomial)    , derivative: None                        , description: "Newton's method on z^3 - 1 (or the polynomial set)" },
+    Formula { name: "NOVA"      , function: &NOVA      , degree: 2.0, bailout: 1e6, escape_norm: "STEP"              , start: ONE , polynomial: Some(&NOVA_polynomial)      , derivative: None                        , description: "Newton's method on z^3 - 1 (or the polynomial set) plus c, starting at z = 1" },
+    Formula { name: "MULTIBROT" , function: &MULTIBROT , degree: 3.0, bailout: 2.0, escape_norm: "EUCLIDEAN"         , start: None, polynomial: Some(&MULTIBROT_polynomial) , derivative: Some(&MULTIBROT_derivative) , description: "Multibrot z^3 + c (or z^n + c with --power, any polynomial of z plus c with --polynomial)" },
+    Formula { name: "MAGNET1"   , function: &MAGNET1   , degree: 2.0, bailout: 1e3, escape_norm: "ESCAPE_OR_CONVERGE", start: ZERO, polynomial: None                        , derivative: None                        , description: "Magnet type I, ((z^2 + c - 1) / (2z + c - 2))^2" },
+    Formula { name: "MAGNET2"   , function: &MAGNET2   , degree: 2.0, bailout: 1e3, escape_norm: "ESCAPE_OR_CONVERGE", start: ZERO, polynomial: None                        , derivative: None                        , description: "Magnet type II" },
 ];
 
 /// Function for getting generator formula from FORMULAS const
-pub fn get_formula(formula: &str) -> &dyn Fn(structs::Complex, structs::Complex) -> structs::Complex {
+pub fn get_formula(formula: &str) -> Result<&'static Formula, KyrosError> {
 
     // Tries to find function in FORMULAS const
-    for (key, value, _) in FORMULAS.iter() {
-        if key == &formula {
-            return value;
+    for value in FORMULAS.iter() {
+        if value.name == formula {
+            return Ok(value);
         }
     }
 
-    let formula_string: String = FORMULAS
-        .iter()
-        .map(|v| format!("  {}\t{}", v.0, v.2))
-        .collect::<Vec<String>>()
-        .join("\n");
-
-    // If not found throw error
-    Args::command().error(
-        ErrorKind::InvalidValue,
-        format!("Function generation method '{}' not found!\n\nAllowed Formulas:\n{}", formula, formula_string)
-    ).exit();
+    // If not found return error
+    return Err(KyrosError::not_found(
+        "Function generation method",
+        "Formulas",
+        formula,
+        FORMULAS.iter().map(|v| (v.name, v.description)),
+    ));
 }
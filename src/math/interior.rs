@@ -0,0 +1,146 @@
+#![allow(non_snake_case)]
+
+use super::super::structs::Complex;
+use super::measurement::HUE_CYCLE;
+use crate::error::KyrosError;
+
+use std::f64::consts::PI;
+
+/*
+# Purpose
+This section of the code is for defining the different ways the orbits that never
+escape (the interior of the set) are turned into a value to color them with. Once the
+orbit runs out of iterations, it is followed a little longer to find the cycle it settled
+into (its attractor), which the interior functions measure.
+*/
+
+/// Longest cycle looked for when following an orbit past its last iteration
+const MAX_PERIOD: u64 = 64;
+
+/// Distance (relative to |z|) two points of the orbit need to be within to count as the same point
+const PERIOD_TOLERANCE: f64 = 1e-6;
+
+/// Cycle an interior orbit settled into
+#[derive(Debug, Clone, Copy)]
+pub struct Attractor {
+    pub z: Complex,                     // Value the orbit ended on
+    pub period: Option<u64>,            // Length of the cycle, if one was found within MAX_PERIOD steps
+    pub angle: f64,                     // Circular mean of the angles of the points of the cycle (or of the steps followed)
+    pub multiplier: Option<Complex>,    // Product of the derivatives around the cycle, |multiplier| < 1 for attracting ones
+}
+
+/// Follows the orbit of `z` under `function` to find the cycle it settled into.
+/// The derivatives are taken numerically, so formulas without one (such as BS) still get a value.
+pub fn find_attractor(function: &dyn Fn(Complex, Complex) -> Complex, c: Complex, z: Complex) -> Attractor {
+    let tolerance = PERIOD_TOLERANCE * z.abs().max(1.0);
+
+    // Angles are averaged as unit vectors (a circular mean), so +179° & -179° average to 180° rather than 0°
+    let mut point = z;
+    let (mut sin_sum, mut cos_sum) = (0.0, 0.0);
+    let mut period = None;
+    for step in 1..=MAX_PERIOD {
+        let (sin, cos) = point.arg().sin_cos();
+        sin_sum += sin;
+        cos_sum += cos;
+        point = function(c, point);
+        if !(point.real.is_finite() && point.imaginary.is_finite()) {
+            break;
+        }
+        if (point - z).abs() < tolerance {
+            period = Some(step);
+            break;
+        }
+    }
+    let angle = f64::atan2(sin_sum, cos_sum);
+
+    let multiplier = period.map(|period| {
+        let mut product = Complex { real: 1.0, imaginary: 0.0 };
+        let mut point = z;
+        for _ in 0..period {
+            product = product * numeric_derivative(&|z| function(c, z), point);
+            point = function(c, point);
+        }
+        product
+    });
+
+    return Attractor { z, period, angle, multiplier };
+}
+
+/// Gets the derivative of `function` at `z` with a central difference
+pub fn numeric_derivative(function: &dyn Fn(Complex) -> Complex, z: Complex) -> Complex {
+    let step = 1e-7 * z.abs().max(1.0);
+    let h = Complex { real: step, imaginary: 0.0 };
+    let difference = function(z + h) - function(z - h);
+    return Complex { real: difference.real / (2.0 * step), imaginary: difference.imaginary / (2.0 * step) };
+}
+
+fn FINAL_Z(attractor: &Attractor) -> f64 {
+    return 0.5 * HUE_CYCLE * attractor.z.abs();
+}
+
+fn PERIOD(attractor: &Attractor) -> f64 {
+    return HUE_CYCLE * attractor.period.unwrap_or(0) as f64 / 8.0;
+}
+
+fn ANGLE(attractor: &Attractor) -> f64 {
+    return HUE_CYCLE * (attractor.angle + PI) / (2.0 * PI);
+}
+
+fn MULTIPLIER(attractor: &Attractor) -> f64 {
+    return match attractor.multiplier {
+        Some(multiplier) => HUE_CYCLE * multiplier.abs().min(1.0),
+        None => HUE_CYCLE,
+    };
+}
+
+/// Type of every interior function
+pub type InteriorFn = dyn Fn(&Attractor) -> f64 + Sync;
+
+/// Interior function of a render along with its formula, `(c, last z) -> value`
+pub type InteriorPass<'a> = dyn Fn(Complex, Complex) -> f64 + Sync + 'a;
+
+/// Sets Bootleg hashmap for interior functions, BLACK leaves the interior uncolored
+pub const INTERIORS: [(&str, Option<&InteriorFn>, &str);5] = [
+    ("BLACK"     , None              , "\tLeaves the interior black"),
+    ("FINAL_Z"   , Some(&FINAL_Z)    , "Colors by |z| at the last iteration"),
+    ("PERIOD"    , Some(&PERIOD)     , "Colors by the length of the cycle the orbit settles into"),
+    ("ANGLE"     , Some(&ANGLE)      , "\tColors by the average angle of the points of the cycle"),
+    ("MULTIPLIER", Some(&MULTIPLIER) , "Colors by |multiplier| of the cycle, how strongly it attracts"),
+];
+
+/// Function for getting the interior function from config, BLACK when none is set
+pub fn get_interior(interior: Option<&str>) -> Result<Option<&'static InteriorFn>, KyrosError> {
+    let interior = interior.unwrap_or("BLACK");
+
+    // Tries to find function in INTERIORS const
+    for (key, value, _) in INTERIORS.iter() {
+        if key == &interior {
+            return Ok(*value);
+        }
+    }
+
+    // If not found return error
+    return Err(KyrosError::not_found(
+        "Interior coloring method",
+        "Interiors",
+        interior,
+        INTERIORS.iter().map(|v| (v.0, v.2)),
+    ));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn angle_is_a_circular_mean() {
+        // Period 2 cycle between the two points just either side of the negative real axis
+        let (sin, cos) = (179.0f64.to_radians().sin(), 179.0f64.to_radians().cos());
+        let z = Complex { real: cos, imaginary: sin };
+        let flip = |_: Complex, z: Complex| Complex { real: z.real, imaginary: -z.imaginary };
+
+        let attractor = find_attractor(&flip, z, z);
+        assert_eq!(attractor.period, Some(2));
+        assert!((attractor.angle.abs() - PI).abs() < 1e-9);
+    }
+}
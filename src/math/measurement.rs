@@ -0,0 +1,404 @@
+#![allow(non_snake_case)]
+
+use super::super::structs::{Complex, Config};
+use super::interior::InteriorPass;
+use crate::error::KyrosError;
+
+use std::f64::consts::PI;
+
+/*
+# Purpose
+This section of the code is for defining the different ways the orbit of a
+pixel is turned into the single value that gets passed to the color and
+shadow functions.
+*/
+
+/// Range most measurements are scaled to, one full hue rotation of ROTATIONAL
+pub(crate) const HUE_CYCLE: f64 = 40.0;
+
+/// Scale of the log of the distance in pixels given by the DISTANCE measurement, one hue rotation is about 55 pixels
+const DISTANCE_SCALE: f64 = HUE_CYCLE / 4.0;
+
+/// Amount of stripes the STRIPE measurement draws per turn around the origin
+const STRIPE_DENSITY: f64 = 5.0;
+
+/// Smallest bailout of measurements that need a large one, when none is set
+pub const LARGE_BAILOUT: f64 = 256.0;
+
+/// Running values of the orbit of one pixel
+#[derive(Debug, Clone, Copy)]
+pub struct Orbit {
+    pub c: Complex,     // Constant of the orbit
+    pub z: Complex,     // Current value of the orbit
+    pub old_z: Complex, // Value before the last step
+    pub dz: Complex,    // Derivative of z by c (or by the starting z of julia sets), only kept by measurements that use it
+    pub steps: u64,     // Amount of steps taken
+    pub value: f64,     // Value the measurement keeps track of
+    pub escaped: bool,
+}
+
+/// Output of measuring one pixel
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Sample {
+    pub value: f64,    // Value to color the pixel with
+    pub steps: u64,    // Amount of steps the orbit took before escaping (or max_i)
+    pub escaped: bool, // Whether the orbit escaped, interior points never do
+}
+
+fn ITERATIONS_step(orbit: &mut Orbit) {
+    orbit.value += 1.0;
+}
+
+fn ITERATIONS_finish(orbit: &Orbit, _: &Measurer) -> f64 {
+    return orbit.value;
+}
+
+fn SMOOTH_finish(orbit: &Orbit, measurer: &Measurer) -> f64 {
+    if !orbit.escaped || orbit.steps == 0 {
+        return orbit.value;
+    }
+
+    // Orbits that converged (such as the ones of NEWTON) get the fraction of the step they converged on,
+    // from how far below the tolerance of 1 / bailout the last step fell. Their steps shrink with the
+    // power of the degree, so the log of the step grows with it.
+    if STEP(orbit, measurer.escape_radius) {
+        let log_ratio = measurer.escape_radius.ln() / -(orbit.z - orbit.old_z).abs().ln();
+        return orbit.value + (log_ratio.ln() / measurer.degree.ln()).max(-1.0);
+    }
+
+    // Fraction of the step the orbit escaped on, normalized by the bailout
+    // so the color bands line up for any radius. Escape norms other than EUCLIDEAN
+    // can stop short of the bailout, those orbits count as escaping right at it.
+    let log_ratio = (orbit.z.norm_sqr().ln() / (2.0 * measurer.escape_radius.ln())).max(1.0);
+    return orbit.value + 1.0 - log_ratio.ln() / measurer.degree.ln();
+}
+
+fn TRAVEL_DISTANCE_step(orbit: &mut Orbit) {
+    orbit.value += (
+        (orbit.z.real - orbit.old_z.real) * (orbit.z.real - orbit.old_z.real) +
+        (orbit.z.imaginary - orbit.old_z.imaginary) * (orbit.z.imaginary - orbit.old_z.imaginary)
+    ).sqrt();
+}
+
+fn MIN_ABS_step(orbit: &mut Orbit) {
+    orbit.value = orbit.value.min(orbit.z.norm_sqr().sqrt());
+}
+
+/// Orbits that escaped before the first step only ever reached their starting z
+fn MIN_ABS_finish(orbit: &Orbit, _: &Measurer) -> f64 {
+    if orbit.steps == 0 {
+        return 0.5 * HUE_CYCLE * orbit.z.abs();
+    }
+    return 0.5 * HUE_CYCLE * orbit.value;
+}
+
+fn FINAL_ANGLE_finish(orbit: &Orbit, _: &Measurer) -> f64 {
+    let angle = orbit.z.imaginary.atan2(orbit.z.real);
+    return HUE_CYCLE * (angle + PI) / (2.0 * PI);
+}
+
+/// Hue of the point the orbit converged to, its angle turned by its distance from 0
+/// so roots on the same ray still get their own colors
+fn ROOT_finish(orbit: &Orbit, _: &Measurer) -> f64 {
+    let turns = (orbit.z.arg() + PI) / (2.0 * PI) + 0.25 * orbit.z.abs().ln_1p();
+    return HUE_CYCLE * turns.rem_euclid(1.0);
+}
+
+fn STRIPE_step(orbit: &mut Orbit) {
+    let angle = orbit.z.imaginary.atan2(orbit.z.real);
+    orbit.value += 0.5 * (STRIPE_DENSITY * angle).sin() + 0.5;
+}
+
+fn STRIPE_finish(orbit: &Orbit, _: &Measurer) -> f64 {
+    if orbit.steps == 0 {
+        return 0.0;
+    }
+    return HUE_CYCLE * orbit.value / orbit.steps as f64;
+}
+
+/// Estimated distance (in math space) from the orbit's pixel to the boundary of the set, 0 for orbits that never escaped
+fn estimated_distance(orbit: &Orbit) -> f64 {
+    let modulus = orbit.z.abs();
+    let distance = modulus * modulus.ln() / orbit.dz.abs();
+    if !orbit.escaped || !distance.is_finite() {
+        return 0.0;
+    }
+    return distance.max(0.0);
+}
+
+/// Gets the distance in pixels a value of the DISTANCE measurement stands for
+pub fn distance_in_pixels(value: f64) -> f64 {
+    return (value / DISTANCE_SCALE).exp() - 1.0;
+}
+
+fn DISTANCE_finish(orbit: &Orbit, measurer: &Measurer) -> f64 {
+    return DISTANCE_SCALE * (estimated_distance(orbit) / measurer.pixel_size).ln_1p();
+}
+
+fn DISTANCE_RAW_finish(orbit: &Orbit, _: &Measurer) -> f64 {
+    return estimated_distance(orbit);
+}
+
+/// Distance of z to the cross shaped trap along both axes
+fn trap_distance(z: Complex) -> f64 {
+    return z.real.abs().min(z.imaginary.abs());
+}
+
+fn ORBIT_TRAP_step(orbit: &mut Orbit) {
+    orbit.value = orbit.value.min(trap_distance(orbit.z));
+}
+
+/// Orbits that escaped before the first step only ever reached their starting z
+fn ORBIT_TRAP_finish(orbit: &Orbit, _: &Measurer) -> f64 {
+    let distance = match orbit.steps {
+        0 => trap_distance(orbit.z),
+        _ => orbit.value,
+    };
+    return -distance.max(1e-300).ln() * HUE_CYCLE / 10.0;
+}
+
+/// Type of the function run after every step of an orbit
+pub type StepFn = dyn Fn(&mut Orbit) + Sync;
+
+/// Type of the function turning a finished orbit into the value of its pixel
+pub type FinishFn = dyn Fn(&Orbit, &Measurer) -> f64 + Sync;
+
+/// Entry of the MEASUREMENTS table
+pub struct Measurement {
+    pub name: &'static str,
+    pub initial: f64, // Value every orbit starts with
+    pub step: &'static StepFn,
+    pub finish: &'static FinishFn,
+    pub uses_bailout: bool, // Escapes at a bailout of at least LARGE_BAILOUT, when none is set
+    pub uses_derivative: bool, // Keeps track of dz of the orbit
+    pub colors_interior: bool, // Orbits that never escape are colored by their value too, instead of black
+    pub description: &'static str,
+}
+
+/// Sets Bootleg hashmap for measurements
+pub const MEASUREMENTS: [Measurement;10] = [
+    Measurement { name: "ITERATIONS"      , initial: 0.0          , step: &ITERATIONS_step      , finish: &ITERATIONS_finish  , uses_bailout: false, uses_derivative: false, colors_interior: false, description: "Amount of iterations before escaping" },
+    Measurement { name: "SMOOTH"          , initial: 0.0          , step: &ITERATIONS_step      , finish: &SMOOTH_finish      , uses_bailout: true , uses_derivative: false, colors_interior: false, description: "\tFractional iteration count, without color bands (escapes at the bailout)" },
+    Measurement { name: "TRAVEL_DISTANCE" , initial: 0.0          , step: &TRAVEL_DISTANCE_step , finish: &ITERATIONS_finish  , uses_bailout: false, uses_derivative: false, colors_interior: true , description: "Distance the orbit travels before escaping" },
+    Measurement { name: "MIN_ABS"         , initial: f64::INFINITY, step: &MIN_ABS_step         , finish: &MIN_ABS_finish     , uses_bailout: false, uses_derivative: false, colors_interior: false, description: "\tSmallest |z| the orbit reaches" },
+    Measurement { name: "FINAL_ANGLE"     , initial: 0.0          , step: &|_| {}               , finish: &FINAL_ANGLE_finish , uses_bailout: false, uses_derivative: false, colors_interior: false, description: "Angle (arg z) of the orbit when it escapes" },
+    Measurement { name: "STRIPE"          , initial: 0.0          , step: &STRIPE_step          , finish: &STRIPE_finish      , uses_bailout: true , uses_derivative: false, colors_interior: false, description: "\tAverage of a stripe function of arg z over the orbit" },
+    Measurement { name: "ORBIT_TRAP"      , initial: f64::INFINITY, step: &ORBIT_TRAP_step      , finish: &ORBIT_TRAP_finish  , uses_bailout: false, uses_derivative: false, colors_interior: false, description: "Closest distance of the orbit to the axes (log scale)" },
+    Measurement { name: "ROOT"            , initial: 0.0          , step: &|_| {}               , finish: &ROOT_finish        , uses_bailout: false, uses_derivative: false, colors_interior: false, description: "\tThe root (or point) the orbit converged to, for convergent formulas" },
+    Measurement { name: "DISTANCE"        , initial: 0.0          , step: &|_| {}               , finish: &DISTANCE_finish    , uses_bailout: true , uses_derivative: true , colors_interior: false, description: "Estimated distance to the boundary in pixels (log scale), draw lines with the BOUNDARY shadow" },
+    Measurement { name: "DISTANCE_RAW"    , initial: 0.0          , step: &|_| {}               , finish: &DISTANCE_RAW_finish, uses_bailout: true , uses_derivative: true , colors_interior: false, description: "Estimated distance to the boundary in math space, (color with --normalize LOG)" },
+];
+
+/// Function for getting the measurement from config
+pub fn get_measurement(measurement: &str) -> Result<&'static Measurement, KyrosError> {
+
+    // Tries to find function in MEASUREMENTS const
+    for value in MEASUREMENTS.iter() {
+        if value.name == measurement {
+            return Ok(value);
+        }
+    }
+
+    // If not found return error
+    return Err(KyrosError::not_found(
+        "Measurement",
+        "Measurements",
+        measurement,
+        MEASUREMENTS.iter().map(|v| (v.name, v.description)),
+    ));
+}
+
+fn EUCLIDEAN(orbit: &Orbit, radius: f64) -> bool {
+    return orbit.z.is_greater(radius);
+}
+
+fn REAL(orbit: &Orbit, radius: f64) -> bool {
+    return orbit.z.real.abs() > radius;
+}
+
+fn IMAGINARY(orbit: &Orbit, radius: f64) -> bool {
+    return orbit.z.imaginary.abs() > radius;
+}
+
+fn MANHATTAN(orbit: &Orbit, radius: f64) -> bool {
+    return orbit.z.real.abs() + orbit.z.imaginary.abs() > radius;
+}
+
+/// Stops once the orbit settles down (its last step is smaller than 1 / bailout),
+/// for fractals whose orbits converge instead of escaping
+fn STEP(orbit: &Orbit, radius: f64) -> bool {
+    return orbit.steps > 0 && (orbit.z - orbit.old_z).norm_sqr() * radius * radius < 1.0;
+}
+
+/// Stops once the orbit escapes or settles down, for fractals with both (such as the magnets)
+fn ESCAPE_OR_CONVERGE(orbit: &Orbit, radius: f64) -> bool {
+    return EUCLIDEAN(orbit, radius) || STEP(orbit, radius);
+}
+
+/// Type of every escape test, `(orbit, bailout) -> escaped`
+pub type EscapeFn = dyn Fn(&Orbit, f64) -> bool + Sync;
+
+/// Sets Bootleg hashmap for escape tests
+pub const ESCAPE_NORMS: [(&str, &EscapeFn, &str);6] = [
+    ("EUCLIDEAN"  , &EUCLIDEAN  , "Escapes once |z| > bailout"),
+    ("REAL"       , &REAL       , "\tEscapes once |re z| > bailout"),
+    ("IMAGINARY"  , &IMAGINARY  , "Escapes once |im z| > bailout"),
+    ("MANHATTAN"  , &MANHATTAN  , "Escapes once |re z| + |im z| > bailout"),
+    ("STEP"       , &STEP       , "\tConverges once the step the orbit takes is small, |z - previous z| < 1 / bailout"),
+    ("ESCAPE_OR_CONVERGE" , &ESCAPE_OR_CONVERGE , "Stops once either EUCLIDEAN or STEP does"),
+];
+
+/// Function for getting the escape test from its name
+pub fn get_escape_norm(escape_norm: &str) -> Result<&'static EscapeFn, KyrosError> {
+
+    // Tries to find function in ESCAPE_NORMS const
+    for (key, value, _) in ESCAPE_NORMS.iter() {
+        if key == &escape_norm {
+            return Ok(value);
+        }
+    }
+
+    // If not found return error
+    return Err(KyrosError::not_found(
+        "Escape norm",
+        "Escape norms",
+        escape_norm,
+        ESCAPE_NORMS.iter().map(|v| (v.0, v.2)),
+    ));
+}
+
+/// Measurement picked for a render, along with what it needs from the config & formula
+pub struct Measurer {
+    pub measurement: &'static Measurement,
+    pub escape_norm: &'static EscapeFn,
+    pub escape_radius: f64,
+    pub degree: f64,
+    pub pixel_size: f64,                        // Distance in math space between two neighbouring pixels
+    pub derivative: Option<Box<DerivativeFn>>,  // Derivatives of the formula, for measurements that keep track of dz
+    pub start_dz: Complex,                      // Derivative of the starting z, 0 when it doesn't depend on c
+}
+
+/// Type of the derivatives of a formula, `(c, z) -> (df/dz, df/dc)`
+pub type DerivativeFn = dyn Fn(Complex, Complex) -> (Complex, Complex) + Sync + Send;
+
+impl Measurer {
+    /// Sets up the measurement from config. `degree`, `bailout` & `escape_norm` are the ones of
+    /// the formula used, the bailout & escape norm of the config replace them when set.
+    pub fn new(config: &Config, degree: f64, bailout: f64, escape_norm: &str) -> Result<Measurer, KyrosError> {
+        let measurement = get_measurement(config.measurement.as_str())?;
+        let escape_radius = match config.bailout {
+            Some(bailout) => bailout,
+            None if measurement.uses_bailout => bailout.max(LARGE_BAILOUT),
+            None => bailout,
+        };
+        if !(escape_radius > 0.0 && escape_radius.is_finite()) {
+            return Err(KyrosError::InvalidOption(format!(
+                "Bailout '{}' is invalid, it must be a positive number!", escape_radius
+            )));
+        }
+
+        // Measurements such as SMOOTH take the log of the bailout, which has to be positive
+        if measurement.uses_bailout && escape_radius <= 1.0 {
+            return Err(KyrosError::InvalidOption(format!(
+                "Bailout '{}' is too small for the {} measurement, it must be larger than 1!", escape_radius, measurement.name
+            )));
+        }
+
+        return Ok(Measurer {
+            measurement,
+            escape_norm: get_escape_norm(config.escape_norm.as_deref().unwrap_or(escape_norm))?,
+            escape_radius,
+            degree,
+            pixel_size: config.math_frame.pixel_size(config.size_x, config.size_y),
+            derivative: None,
+            start_dz: Complex { real: 1.0, imaginary: 0.0 },
+        });
+    }
+
+    /// Starts the orbit of constant `c` at `z`
+    pub fn start(&self, c: Complex, z: Complex) -> Orbit {
+        return Orbit {
+            c,
+            z,
+            old_z: z,
+            dz: self.start_dz,
+            steps: 0,
+            value: self.measurement.initial,
+            escaped: false,
+        };
+    }
+
+    /// Checks (and remembers) if the orbit has escaped
+    pub fn escape(&self, orbit: &mut Orbit) -> bool {
+        orbit.escaped = (self.escape_norm)(orbit, self.escape_radius);
+        return orbit.escaped;
+    }
+
+    /// Moves the orbit on to its next value
+    pub fn step(&self, orbit: &mut Orbit, z: Complex) {
+        if let Some(derivative) = &self.derivative {
+            let (dfdz, dfdc) = derivative(orbit.c, orbit.z);
+            orbit.dz = dfdz * orbit.dz + dfdc;
+        }
+        orbit.old_z = orbit.z;
+        orbit.z = z;
+        orbit.steps += 1;
+        (self.measurement.step)(orbit);
+    }
+
+    /// Gets the sample of a finished orbit
+    pub fn finish(&self, orbit: &Orbit) -> Sample {
+        return Sample {
+            value: (self.measurement.finish)(orbit, self),
+            steps: orbit.steps,
+            escaped: orbit.escaped,
+        };
+    }
+
+    /// Gets the sample of a finished orbit of `c`, orbits that never escaped get the value of `interior` (if any)
+    pub fn finish_interior(&self, orbit: &Orbit, c: Complex, interior: Option<&InteriorPass>) -> Sample {
+        let mut sample = self.finish(orbit);
+        if let (false, Some(interior)) = (sample.escaped, interior) {
+            sample.value = interior(c, orbit.z);
+        }
+        return sample;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_config(measurement: &str, bailout: Option<f64>) -> Config {
+        return Config { size_x: 8, size_y: 8, measurement: measurement.to_string(), bailout, ..Config::default() };
+    }
+
+    #[test]
+    fn bailouts() {
+        for bailout in [0.5, 1.0] {
+            for measurement in MEASUREMENTS.iter() {
+                let result = Measurer::new(&test_config(measurement.name, Some(bailout)), 2.0, 2.0, "EUCLIDEAN");
+                assert_eq!(result.is_err(), measurement.uses_bailout, "{} {}", measurement.name, bailout);
+            }
+        }
+        for bailout in [0.0, -2.0, f64::NAN, f64::INFINITY] {
+            assert!(Measurer::new(&test_config("ITERATIONS", Some(bailout)), 2.0, 2.0, "EUCLIDEAN").is_err());
+        }
+
+        // Measurements that need a large bailout get one, unless it is set
+        assert_eq!(Measurer::new(&test_config("SMOOTH", None), 2.0, 2.0, "EUCLIDEAN").unwrap().escape_radius, LARGE_BAILOUT);
+        assert_eq!(Measurer::new(&test_config("SMOOTH", Some(1.5)), 2.0, 2.0, "EUCLIDEAN").unwrap().escape_radius, 1.5);
+        assert_eq!(Measurer::new(&test_config("ITERATIONS", None), 2.0, 2.0, "EUCLIDEAN").unwrap().escape_radius, 2.0);
+    }
+
+    #[test]
+    fn instant_escapes_are_finite() {
+        for measurement in MEASUREMENTS.iter() {
+            let measurer = Measurer::new(&test_config(measurement.name, None), 2.0, 2.0, "EUCLIDEAN").unwrap();
+            let point = Complex { real: 300.0, imaginary: 400.0 };
+            let mut orbit = measurer.start(point, point);
+            assert!(measurer.escape(&mut orbit));
+            assert!(measurer.finish(&orbit).value.is_finite(), "{}", measurement.name);
+        }
+    }
+}
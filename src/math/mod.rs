@@ -1,2 +1,8 @@
 pub mod formula;
+pub mod expression;
+pub mod measurement;
+pub mod interior;
+pub mod bigfloat;
+pub mod floatexp;
+pub mod perturbation;
 
@@ -0,0 +1,513 @@
+#![allow(non_snake_case)]
+
+/*
+# Purpose
+Deep zoom rendering through perturbation theory. One reference orbit is
+calculated at arbitrary precision for the center of the image, every pixel
+then only follows its (small) difference to that orbit, which fits in an f64
+(or a FloatExp for the very deepest zooms).
+*/
+
+use super::super::structs::{Complex, Config};
+use crate::error::KyrosError;
+use super::interior::InteriorPass;
+use super::measurement::{Measurer, Sample};
+use super::bigfloat::{BigComplex, BigFloat};
+use super::floatexp::{FloatExp, Real};
+
+use std::ops::{ Add, Sub, Mul };
+
+/// Pixel sizes below this are out of reach of plain f64 math
+const DEEP_PIXEL_SIZE: f64 = 1e-13;
+
+/// Pixel sizes below this need deltas with an extended exponent
+const EXTENDED_PIXEL_SIZE: f64 = 1e-290;
+
+/// Pixels are glitched once they get this much closer to 0 than the reference (Pauldelbrot's criterion)
+const GLITCH_TOLERANCE: f64 = 1e-6;
+
+/// Largest bailout (& center) of deep zooms, so every value of the reference orbit fits in a BigFloat
+const MAX_DEEP_BAILOUT: f64 = 1e9;
+
+/// Amount of extra references tried on each row for fixing glitched pixels
+const MAX_GLITCH_REFERENCES: usize = 8;
+
+/// Complex number made of any `Real`, used for the difference of a pixel to the reference
+#[derive(Debug, Clone, Copy)]
+pub struct Delta<T: Real> {
+    pub real: T,
+    pub imaginary: T,
+}
+
+impl<T: Real> Delta<T> {
+    fn from_complex(value: Complex) -> Delta<T> {
+        return Delta { real: T::from_f64(value.real), imaginary: T::from_f64(value.imaginary) };
+    }
+
+    fn to_complex(self) -> Complex {
+        return Complex { real: self.real.to_f64(), imaginary: self.imaginary.to_f64() };
+    }
+}
+
+impl<T: Real> Add for Delta<T> {
+    type Output = Delta<T>;
+
+    fn add(self, other: Delta<T>) -> Delta<T> {
+        return Delta { real: self.real + other.real, imaginary: self.imaginary + other.imaginary };
+    }
+}
+
+impl<T: Real> Sub for Delta<T> {
+    type Output = Delta<T>;
+
+    fn sub(self, other: Delta<T>) -> Delta<T> {
+        return Delta { real: self.real - other.real, imaginary: self.imaginary - other.imaginary };
+    }
+}
+
+impl<T: Real> Mul for Delta<T> {
+    type Output = Delta<T>;
+
+    fn mul(self, other: Delta<T>) -> Delta<T> {
+        return Delta {
+            real: self.real * other.real - self.imaginary * other.imaginary,
+            imaginary: self.real * other.imaginary + self.imaginary * other.real,
+        };
+    }
+}
+
+/// Gets |c + d| - |c| without losing the precision of the small d
+fn diffabs<T: Real>(c: f64, d: T) -> T {
+    let sum = T::from_f64(c) + d;
+    if c >= 0.0 {
+        if !sum.is_negative() { return d; }
+        return -(T::from_f64(2.0 * c) + d);
+    }
+    if sum.is_negative() || sum == T::zero() { return -d; }
+    return T::from_f64(2.0 * c) + d;
+}
+
+/// Gets (Z + δ)^2 - Z^2 = (2Z + δ)δ, the shared core of every formula here
+fn square_delta<T: Real>(Z: Complex, dz: Delta<T>) -> Delta<T> {
+    let two_Z: Delta<T> = Delta::from_complex(Complex { real: 2.0 * Z.real, imaginary: 2.0 * Z.imaginary });
+    return (two_Z + dz) * dz;
+}
+
+/// Gets the change of the imaginary part of z^2 = 2xy
+fn cross_delta<T: Real>(Z: Complex, dz: Delta<T>) -> T {
+    let two = T::from_f64(2.0);
+    return two * (T::from_f64(Z.real) * dz.imaginary + T::from_f64(Z.imaginary) * dz.real + dz.real * dz.imaginary);
+}
+
+/* Reference orbit steps at arbitrary precision, these match FORMULAS exactly */
+
+fn SD_reference(c: &BigComplex, z: &BigComplex) -> BigComplex {
+    return &z.square() + c;
+}
+
+fn R_reference(c: &BigComplex, z: &BigComplex) -> BigComplex {
+    let mut new_z = &z.square() + c;
+    new_z.imaginary = &new_z.imaginary - &z.real;
+    new_z.real = &new_z.real - &z.imaginary;
+    return new_z;
+}
+
+fn ABR_reference(c: &BigComplex, z: &BigComplex) -> BigComplex {
+    let mut new_z = z.square();
+    if new_z.imaginary.is_negative() {
+        new_z.imaginary = new_z.imaginary.neg();
+    }
+    new_z.imaginary = &new_z.imaginary - &z.real;
+    new_z.real = &new_z.real - &z.imaginary;
+    return &new_z + c;
+}
+
+fn BS_reference(c: &BigComplex, z: &BigComplex) -> BigComplex {
+    let mut new_z = z.square();
+    if !new_z.imaginary.is_negative() {
+        new_z.imaginary = new_z.imaginary.neg();
+    }
+    return &new_z + c;
+}
+
+fn SYM_reference(c: &BigComplex, z: &BigComplex) -> BigComplex {
+    return &(&z.square() + c) - z;
+}
+
+/* Perturbed steps, giving the new δ from the reference value Z, the old δ & δc */
+
+fn SD_delta<T: Real>(Z: Complex, dz: Delta<T>, dc: Delta<T>) -> Delta<T> {
+    return square_delta(Z, dz) + dc;
+}
+
+fn R_delta<T: Real>(Z: Complex, dz: Delta<T>, dc: Delta<T>) -> Delta<T> {
+    let mut new_dz = square_delta(Z, dz) + dc;
+    new_dz.imaginary = new_dz.imaginary - dz.real;
+    new_dz.real = new_dz.real - dz.imaginary;
+    return new_dz;
+}
+
+fn ABR_delta<T: Real>(Z: Complex, dz: Delta<T>, dc: Delta<T>) -> Delta<T> {
+    let square = square_delta(Z, dz);
+    return Delta {
+        real: square.real - dz.imaginary + dc.real,
+        imaginary: diffabs(2.0 * Z.real * Z.imaginary, cross_delta(Z, dz)) - dz.real + dc.imaginary,
+    };
+}
+
+fn BS_delta<T: Real>(Z: Complex, dz: Delta<T>, dc: Delta<T>) -> Delta<T> {
+    let square = square_delta(Z, dz);
+    return Delta {
+        real: square.real + dc.real,
+        imaginary: -diffabs(2.0 * Z.real * Z.imaginary, cross_delta(Z, dz)) + dc.imaginary,
+    };
+}
+
+fn SYM_delta<T: Real>(Z: Complex, dz: Delta<T>, dc: Delta<T>) -> Delta<T> {
+    return square_delta(Z, dz) - dz + dc;
+}
+
+/// Step of the reference orbit at arbitrary precision, `(c, z) -> z`
+type ReferenceFn = fn(&BigComplex, &BigComplex) -> BigComplex;
+
+/// Step of a pixel's delta, `(Z, δz, δc) -> δz`
+type DeltaFn<T> = fn(Complex, Delta<T>, Delta<T>) -> Delta<T>;
+
+/// Sets Bootleg hashmap for the formulas that support deep zooms
+///   PERTURBATIONS.0 == Key Value (same as in FORMULAS)
+///   PERTURBATIONS.1 == Reference Orbit Function
+///   PERTURBATIONS.2 == Delta Function
+fn perturbations<T: Real>() -> [(&'static str, ReferenceFn, DeltaFn<T>);5] {
+    return [
+        ("SD"  , SD_reference  , SD_delta::<T>  ),
+        ("R"   , R_reference   , R_delta::<T>   ),
+        ("ABR" , ABR_reference , ABR_delta::<T> ),
+        ("BS"  , BS_reference  , BS_delta::<T>  ),
+        ("SYM" , SYM_reference , SYM_delta::<T> ),
+    ];
+}
+
+/// Function for getting the deep zoom functions of a formula
+fn get_perturbation<T: Real>(formula: &str) -> Option<(ReferenceFn, DeltaFn<T>)> {
+    return perturbations::<T>()
+        .iter()
+        .find(|(key, _, _)| *key == formula)
+        .map(|(_, reference, delta)| (*reference, *delta));
+}
+
+/// Renderer for zooms deeper than f64 can resolve
+pub struct DeepZoom {
+    reference_function: ReferenceFn,
+    escape_radius: f64,
+    frac_limbs: usize,
+    center: BigComplex,
+    c_julia: Option<BigComplex>,
+    orbit: Vec<Complex>, // Reference orbit, starting at 0 for the mandelbrot & at the center for julia sets
+    pixel_size: FloatExp,
+    extended: bool, // Whether the deltas need FloatExp
+}
+
+impl DeepZoom {
+
+    /// Sets up the deep zoom renderer, if the config needs (or asks for) one
+    pub fn new(config: &Config, measurer: &Measurer) -> Result<Option<DeepZoom>, KyrosError> {
+        let frame = &config.math_frame;
+
+        // Works the pixel size out without leaving the f64 range
+        let shorter_side = (config.size_x.min(config.size_y) as f64 - 1.0).max(1.0);
+        let pixel_size = FloatExp::from_f64(4.0 / shorter_side) * FloatExp::from_f64(2f64.powi(64) / frame.zoom).scale(-64);
+
+        let center_size = frame.center_re.abs().max(frame.center_im.abs()).max(1.0);
+        if !config.deep_zoom && pixel_size.to_f64() >= DEEP_PIXEL_SIZE * center_size {
+            return Ok(None);
+        }
+
+        // Formula expressions have no deep zoom version, & f64 would only render noise this deep
+        let perturbation = match config.formula_expr {
+            Some(_) => None,
+            None => get_perturbation::<f64>(config.gen_formula.as_str()),
+        };
+        let reference_function = match perturbation {
+            Some((reference_function, _)) => reference_function,
+            None => {
+                let formula = config.formula_expr.as_ref().unwrap_or(&config.gen_formula);
+                return Err(KyrosError::NoDeepZoom(formula.clone()));
+            },
+        };
+
+        // The reference orbit is held in the integer limbs of BigFloat, which the square of
+        // the bailout (the largest value the orbit steps to) has to fit in
+        if measurer.escape_radius > MAX_DEEP_BAILOUT {
+            return Err(KyrosError::InvalidOption(format!(
+                "Bailout '{}' is too large for deep zooms, it can be at most {}!", measurer.escape_radius, MAX_DEEP_BAILOUT
+            )));
+        }
+        let c_size = config.c_init.map_or(0.0, |c| c.real.abs().max(c.imaginary.abs()));
+        if center_size.max(c_size) > MAX_DEEP_BAILOUT {
+            return Err(KyrosError::InvalidGeometry(format!(
+                "Points further than {} from 0 can't be deep zoomed into!", MAX_DEEP_BAILOUT
+            )));
+        }
+
+        // Enough bits for the pixel size, with plenty to spare for the orbit
+        let (_, pixel_exponent) = pixel_size.parts();
+        let frac_limbs = BigFloat::limbs_for_bits((64 - pixel_exponent).max(64) as u32);
+
+        let center = match &frame.center_exact {
+            Some((real, imaginary)) => BigComplex {
+                real: BigFloat::parse(real, frac_limbs).unwrap_or(BigFloat::from_f64(frame.center_re, frac_limbs)),
+                imaginary: BigFloat::parse(imaginary, frac_limbs).unwrap_or(BigFloat::from_f64(frame.center_im, frac_limbs)),
+            },
+            None => BigComplex::from_complex(Complex { real: frame.center_re, imaginary: frame.center_im }, frac_limbs),
+        };
+        let c_julia = config.c_init.map(|c| BigComplex::from_complex(c, frac_limbs));
+
+        let mut deep_zoom = DeepZoom {
+            reference_function,
+            escape_radius: measurer.escape_radius,
+            frac_limbs,
+            center,
+            c_julia,
+            orbit: Vec::new(),
+            pixel_size,
+            extended: pixel_size.to_f64() < EXTENDED_PIXEL_SIZE,
+        };
+        deep_zoom.orbit = deep_zoom.reference_orbit(&deep_zoom.center, config.max_i);
+        return Ok(Some(deep_zoom));
+    }
+
+    /// Calculates the orbit of `point` at full precision.
+    /// Julia sets start at the point itself, the mandelbrot starts at 0 so
+    /// that any pixel can rebase onto the start of the orbit.
+    fn reference_orbit(&self, point: &BigComplex, max_i: u64) -> Vec<Complex> {
+        let (c, mut z) = match &self.c_julia {
+            Some(c_julia) => (c_julia.clone(), point.clone()),
+            None => (point.clone(), BigComplex::from_complex(Complex { real: 0.0, imaginary: 0.0 }, self.frac_limbs)),
+        };
+
+        let mut orbit = vec![z.to_complex()];
+        for _ in 0..=max_i {
+            z = (self.reference_function)(&c, &z);
+            let value = z.to_complex();
+            orbit.push(value);
+            if value.is_greater(self.escape_radius) { break; }
+        }
+        return orbit;
+    }
+
+    /// Function for measuring one row of the image, the same way `measure_row` does
+    pub fn measure_row(&self, config: &Config, measurer: &Measurer, interior: Option<&InteriorPass>, i: u32, samples: &mut [Sample]) {
+        if self.extended {
+            self.measure_row_with::<FloatExp>(config, measurer, interior, i, samples);
+        }
+        else {
+            self.measure_row_with::<f64>(config, measurer, interior, i, samples);
+        }
+    }
+
+    fn measure_row_with<T: Real>(&self, config: &Config, measurer: &Measurer, interior: Option<&InteriorPass>, i: u32, samples: &mut [Sample]) {
+        let (_, delta_function) = get_perturbation::<T>(config.gen_formula.as_str()).unwrap();
+        let pixel_size = T::from_floatexp(self.pixel_size);
+
+        // Offset of every pixel from the center of the image
+        let offsets: Vec<Delta<T>> = (0..samples.len())
+            .map(|j| {
+                let steps = config.pixel_steps(j as f64, i as f64);
+                Delta {
+                    real: T::from_f64(steps.real) * pixel_size,
+                    imaginary: T::from_f64(steps.imaginary) * pixel_size,
+                }
+            })
+            .collect();
+
+        if self.c_julia.is_none() {
+            for (sample, offset) in samples.iter_mut().zip(offsets.iter()) {
+                *sample = self.measure_mandelbrot(config, measurer, interior, delta_function, *offset);
+            }
+            return;
+        }
+
+        // Julia sets can't rebase, glitched pixels get another go with a reference of their own
+        let mut glitched: Vec<usize> = Vec::new();
+        for (j, sample) in samples.iter_mut().enumerate() {
+            match self.measure_julia(config, measurer, interior, delta_function, &self.orbit, offsets[j]) {
+                Some(output) => *sample = output,
+                None => glitched.push(j),
+            }
+        }
+
+        for _ in 0..MAX_GLITCH_REFERENCES {
+            if glitched.is_empty() { break; }
+
+            // Takes the middle glitched pixel as the new reference
+            let reference_offset = offsets[glitched[glitched.len() / 2]];
+            let reference_point = &self.center + &self.offset_to_big(reference_offset);
+            let orbit = self.reference_orbit(&reference_point, config.max_i);
+
+            glitched.retain(|j| {
+                let offset = offsets[*j] - reference_offset;
+                match self.measure_julia(config, measurer, interior, delta_function, &orbit, offset) {
+                    Some(output) => { samples[*j] = output; false },
+                    None => true,
+                }
+            });
+        }
+
+        // The few pixels still glitched are calculated exactly, from a reference at the pixel itself
+        let zero = Delta { real: T::zero(), imaginary: T::zero() };
+        for j in glitched {
+            let reference_point = &self.center + &self.offset_to_big(offsets[j]);
+            let orbit = self.reference_orbit(&reference_point, config.max_i);
+            if let Some(output) = self.measure_julia(config, measurer, interior, delta_function, &orbit, zero) {
+                samples[j] = output;
+            }
+        }
+    }
+
+    /// Converts a pixel offset to full precision
+    fn offset_to_big<T: Real>(&self, offset: Delta<T>) -> BigComplex {
+        let convert = |value: T| {
+            let (mantissa, exponent) = value.to_floatexp().parts();
+            BigFloat::from_scaled_f64(mantissa, exponent, self.frac_limbs)
+        };
+        return BigComplex { real: convert(offset.real), imaginary: convert(offset.imaginary) };
+    }
+
+    /// Measures a mandelbrot pixel, rebasing onto the start of the reference
+    /// whenever the pixel gets closer to 0 than to the reference (Zhuoran's method)
+    fn measure_mandelbrot<T: Real>(&self, config: &Config, measurer: &Measurer, interior: Option<&InteriorPass>, delta_function: DeltaFn<T>, dc: Delta<T>) -> Sample {
+        let orbit = &self.orbit;
+
+        // The first step from 0 lands on c, the starting point of every pixel
+        let mut m = 1;
+        let mut dz = dc;
+        let c = orbit[m] + dz.to_complex();
+        let mut pixel = measurer.start(c, c);
+
+        for _ in 0..config.max_i {
+            if measurer.escape(&mut pixel) { break; }
+
+            if m == orbit.len() - 1 || pixel.z.norm_sqr() < dz.to_complex().norm_sqr() {
+                dz = Delta::from_complex(orbit[m]) + dz;
+                m = 0;
+            }
+            dz = delta_function(orbit[m], dz, dc);
+            m += 1;
+
+            measurer.step(&mut pixel, orbit[m] + dz.to_complex());
+        }
+        return measurer.finish_interior(&pixel, c, interior);
+    }
+
+    /// Measures a julia set pixel, giving None if it glitched
+    fn measure_julia<T: Real>(&self, config: &Config, measurer: &Measurer, interior: Option<&InteriorPass>, delta_function: DeltaFn<T>, orbit: &[Complex], offset: Delta<T>) -> Option<Sample> {
+        let dc = Delta { real: T::zero(), imaginary: T::zero() };
+
+        let mut m = 0;
+        let mut dz = offset;
+        let c = config.c_init.unwrap();
+        let mut pixel = measurer.start(c, orbit[m] + dz.to_complex());
+
+        for _ in 0..config.max_i {
+            if measurer.escape(&mut pixel) { break; }
+
+            if m == orbit.len() - 1 || pixel.z.norm_sqr() < GLITCH_TOLERANCE * orbit[m].norm_sqr() {
+                return None;
+            }
+            dz = delta_function(orbit[m], dz, dc);
+            m += 1;
+
+            measurer.step(&mut pixel, orbit[m] + dz.to_complex());
+        }
+        return Some(measurer.finish_interior(&pixel, c, interior));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::math::formula::get_formula;
+    use crate::structs::MathFrame;
+    use crate::utils::{measure_field, thread_pool};
+
+    fn test_config(deep_zoom: bool) -> Config {
+        return Config {
+            size_x: 64,
+            size_y: 64,
+            max_i: 1000,
+            deep_zoom,
+            math_frame: MathFrame {
+                center_re: -0.743643887037151,
+                center_im: 0.13182590420533,
+                zoom: 1e8,
+                ..MathFrame::default()
+            },
+            threads: 1,
+            ..Config::default()
+        };
+    }
+
+    #[test]
+    fn matches_the_f64_renderer() {
+        let direct_config = test_config(false);
+        let deep_config = test_config(true);
+        let pool = thread_pool(&direct_config).unwrap();
+        let direct = measure_field(&direct_config, &pool).unwrap();
+        let deep = measure_field(&deep_config, &pool).unwrap();
+
+        // Full precision orbits to settle the pixels the two renderers disagree on
+        let formula = get_formula("SD").unwrap();
+        let measurer = Measurer::new(&deep_config, 2.0, formula.bailout, formula.escape_norm).unwrap();
+        let deep_zoom = DeepZoom::new(&deep_config, &measurer).unwrap().unwrap();
+
+        let mut mismatches = 0;
+        for (j, (a, b)) in direct.samples.iter().zip(deep.samples.iter()).enumerate() {
+            let mismatch = a.steps != b.steps || a.escaped != b.escaped;
+            if mismatch { mismatches += 1; }
+            // Checks those (& a sample of the rest) against the exact orbit
+            if !mismatch && j % 17 != 0 { continue; }
+            let steps = deep_config.pixel_steps((j % 64) as f64, (j / 64) as f64);
+            let offset = Delta::<f64> {
+                real: steps.real * deep_zoom.pixel_size.to_f64(),
+                imaginary: steps.imaginary * deep_zoom.pixel_size.to_f64(),
+            };
+            let point = &deep_zoom.center + &deep_zoom.offset_to_big(offset);
+            let orbit = deep_zoom.reference_orbit(&point, deep_config.max_i);
+            let exact_steps = (orbit.len() - 2) as u64;
+            let exact_escaped = exact_steps < deep_config.max_i;
+            assert_eq!(b.escaped, exact_escaped, "pixel {}", j);
+            if exact_escaped {
+                assert_eq!(b.steps, exact_steps, "pixel {}", j);
+            }
+        }
+        assert!(mismatches * 100 < direct.samples.len(), "{} of {} pixels differ", mismatches, direct.samples.len());
+    }
+    #[test]
+    fn values_past_the_integer_limbs_fail() {
+        let config = test_config(true);
+        let measurer = |bailout: f64| Measurer::new(&Config { bailout: Some(bailout), ..config.clone() }, 2.0, 2.0, "EUCLIDEAN").unwrap();
+        assert!(DeepZoom::new(&config, &measurer(MAX_DEEP_BAILOUT)).is_ok());
+        assert!(matches!(DeepZoom::new(&config, &measurer(1e12)), Err(KyrosError::InvalidOption(_))));
+
+        let mut far = config.clone();
+        far.math_frame.center_re = 1e25;
+        far.math_frame.center_exact = Some(("1e25".to_string(), "0".to_string()));
+        assert!(matches!(DeepZoom::new(&far, &measurer(2.0)), Err(KyrosError::InvalidGeometry(_))));
+    }
+
+    #[test]
+    fn formulas_without_perturbation_fail() {
+        let formula = get_formula("SD").unwrap();
+        let mut config = test_config(false);
+        config.formula_expr = Some("z^2 + c".to_string());
+        let measurer = Measurer::new(&config, 2.0, formula.bailout, formula.escape_norm).unwrap();
+        assert!(DeepZoom::new(&config, &measurer).unwrap().is_none());
+
+        // Past f64 precision they'd only render noise
+        config.math_frame.zoom = 1e20;
+        assert!(matches!(DeepZoom::new(&config, &measurer), Err(KyrosError::NoDeepZoom(_))));
+        config.formula_expr = None;
+        config.gen_formula = "NEWTON".to_string();
+        assert!(matches!(DeepZoom::new(&config, &measurer), Err(KyrosError::NoDeepZoom(_))));
+    }
+}
@@ -0,0 +1,99 @@
+/*
+  File for storing the config an image was rendered with inside of the PNG itself,
+  so any saved image can be rendered again.
+*/
+
+use crate::error::KyrosError;
+use crate::structs::Config;
+
+use std::fs::File;
+use std::io::{BufReader, Write};
+
+/// Keyword of the iTXt chunk holding the config as JSON
+pub const CONFIG_KEYWORD: &str = "Kyros Config";
+
+/// Function for setting up a PNG encoder for an RGB image,
+/// with the config stored in its text chunks
+pub fn png_encoder<W: Write>(config: &Config, writer: W, size_x: u32, size_y: u32) -> Result<png::Encoder<'static, W>, KyrosError> {
+    let json = serde_json::to_string(config).map_err(|error| KyrosError::Encoding(error.to_string()))?;
+    let description = format!(
+        "Formula {}, color {}, shadow {}, measurement {}, {} iterations, zoom {} at {} + {}i, frame {}",
+        config.formula_expr.as_ref().unwrap_or(&config.gen_formula),
+        config.color_formula,
+        config.shadow_formula,
+        config.measurement,
+        config.max_i,
+        config.math_frame.zoom,
+        config.math_frame.center_re,
+        config.math_frame.center_im,
+        config.count,
+    );
+
+    let mut encoder = png::Encoder::new(writer, size_x, size_y);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+
+    let encoding_error = |error: png::EncodingError| KyrosError::Encoding(error.to_string());
+    encoder.add_text_chunk("Software".to_string(), format!("Kyros {}", env!("CARGO_PKG_VERSION"))).map_err(encoding_error)?;
+    encoder.add_text_chunk("Description".to_string(), description).map_err(encoding_error)?;
+    encoder.add_itxt_chunk(CONFIG_KEYWORD.to_string(), json).map_err(encoding_error)?;
+    return Ok(encoder);
+}
+
+/// Function for reading back the config stored in a PNG saved by Kyros
+pub fn read_config(path: &str) -> Result<Config, KyrosError> {
+    let file = File::open(path).map_err(|error| KyrosError::io(path, error))?;
+    let invalid = |message: String| KyrosError::InvalidFile { path: path.to_string(), message };
+
+    let reader = png::Decoder::new(BufReader::new(file))
+        .read_info()
+        .map_err(|error| invalid(format!("it isn't a PNG ({})", error)))?;
+
+    let chunk = reader.info().utf8_text
+        .iter()
+        .find(|chunk| chunk.keyword == CONFIG_KEYWORD)
+        .ok_or_else(|| invalid(format!("it has no '{}' text chunk", CONFIG_KEYWORD)))?;
+    let json = chunk.get_text().map_err(|error| invalid(error.to_string()))?;
+
+    return serde_json::from_str(&json).map_err(|error| invalid(format!("the stored config is invalid, {}", error)));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::save::save_png;
+    use crate::structs::MathFrame;
+    use crate::test_utils::temp_path;
+    use crate::utils::eval_function;
+
+    #[test]
+    fn stored_config_reproduces_the_image() {
+        let path = temp_path("reproduce");
+        let config = Config {
+            size_x: 48,
+            size_y: 32,
+            max_i: 300,
+            gen_formula: "R".to_string(),
+            color_formula: "SINUSOIDAL".to_string(),
+            measurement: "SMOOTH".to_string(),
+            hue_offset: 37.5,
+            output: Some(path.clone()),
+            math_frame: MathFrame {
+                center_re: -0.743643887037151,
+                center_im: 0.13182590420533,
+                zoom: 1234.567,
+                rotation: 12.3,
+                ..MathFrame::default()
+            },
+            ..Config::default()
+        };
+        save_png(&config, &eval_function(&config).unwrap()).unwrap();
+
+        let saved = image::open(format!("{}.png", path)).unwrap().to_rgb8();
+        let stored = read_config(&format!("{}.png", path)).unwrap();
+        std::fs::remove_file(format!("{}.png", path)).unwrap();
+
+        assert_eq!(serde_json::to_string(&stored).unwrap(), serde_json::to_string(&config).unwrap());
+        assert!(eval_function(&stored).unwrap().as_raw() == saved.as_raw());
+    }
+}
@@ -1,56 +1,277 @@
 #![allow(non_snake_case)]
 
-use super::*;
-use clap::error::ErrorKind;
-use clap::CommandFactory;
 use base64::{Engine as _, engine::general_purpose};
-use image::codecs::png::PngEncoder;
-use image::ImageEncoder;
-use image::flat::Error;
+use image::codecs::gif::{GifEncoder, Repeat};
+use image::{Delay, Frame, RgbImage};
 
-fn PNG(image_buffer: image::RgbImage, config: &Config) -> Result<(), Error> {
-    image_buffer.save(format!("out#{}.png", config.count)).unwrap();
+use crate::error::KyrosError;
+use crate::metadata::png_encoder;
+use crate::structs::Config;
+use crate::utils::{check_band_coloring, eval_function, eval_rows, measure_field, thread_pool, Measuring};
+
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+fn PNG(config: &Config) -> Result<(), KyrosError> {
+    let image_buffer = eval_function(config)?;
+    return save_png(config, &image_buffer);
+}
+
+/// Saves an image that has already been rendered as PNG, with the config in its text chunks
+pub fn save_png(config: &Config, image_buffer: &RgbImage) -> Result<(), KyrosError> {
+    if config.progress {
+        println!("Saving File...");
+    }
+    let path = config.output_path("png");
+    let file = File::create(&path).map_err(|error| KyrosError::io(&path, error))?;
+    return write_png(config, BufWriter::new(file), image_buffer, &path);
+}
+
+/// Encodes an image as PNG into `writer` (named `path` in errors), with the config in its text chunks
+fn write_png<W: Write>(config: &Config, writer: W, image_buffer: &RgbImage, path: &str) -> Result<(), KyrosError> {
+    let png_error = |error| KyrosError::from_png(path, error);
+    let encoder = png_encoder(config, writer, image_buffer.width(), image_buffer.height())?;
+    let mut writer = encoder.write_header().map_err(png_error)?;
+    writer.write_image_data(image_buffer).map_err(png_error)?;
+    return writer.finish().map_err(png_error);
+}
+
+/// Saves the measured value of every pixel, with the config, so it can be colored later
+fn KYF(config: &Config) -> Result<(), KyrosError> {
+    let field = measure_field(config, &*thread_pool(config)?)?;
+    return field.write_kyf(config, &config.output_path("kyf"));
+}
+
+/// Saves the measured value of every pixel as a NumPy array
+fn NPY(config: &Config) -> Result<(), KyrosError> {
+    let field = measure_field(config, &*thread_pool(config)?)?;
+    return field.write_npy(&config.output_path("npy"));
+}
+
+/// Renders the image in bands of `config.band_rows` rows and feeds each band
+/// straight into the PNG encoder, so only one band is ever held in memory.
+fn STREAM(config: &Config) -> Result<(), KyrosError> {
+    // Checks the config before the file gets created
+    config.validate()?;
+    if config.size_y > config.band_rows.max(1) {
+        check_band_coloring(config)?;
+    }
+
+    // Set up once for every band, deep zooms calculate their reference orbit here
+    let measuring = Measuring::new(config)?;
+
+    let path = config.output_path("png");
+    let file = File::create(&path).map_err(|error| KyrosError::io(&path, error))?;
+
+    let png_error = |error| KyrosError::from_png(&path, error);
+    let encoder = png_encoder(config, BufWriter::new(file), config.size_x, config.size_y)?;
+    let mut writer = encoder.write_header().map_err(png_error)?;
+    let mut stream = writer.stream_writer().map_err(png_error)?;
+
+    let pool = thread_pool(config)?;
+    let row_length = 3 * config.size_x as usize;
+    let band_rows = config.band_rows.max(1);
+    let mut band = vec![0u8; row_length * band_rows.min(config.size_y) as usize];
+
+    for first_row in (0..config.size_y).step_by(band_rows as usize) {
+        let rows = band_rows.min(config.size_y - first_row) as usize;
+        let band = &mut band[..row_length * rows];
+
+        eval_rows(config, &measuring, &pool, first_row, band)?;
+        stream.write_all(band).map_err(|error| KyrosError::io(&path, error))?;
+    }
+    stream.finish().map_err(png_error)?;
+
+    if config.progress {
+        println!();
+    }
     return Ok(());
 }
 
-fn B64(image_buffer: image::RgbImage, _config: &Config) -> Result<(), Error> {
+fn B64(config: &Config) -> Result<(), KyrosError> {
+    let image_buffer = eval_function(config)?;
+    return write_b64(config, &image_buffer);
+}
+
+/// Sends an image that has already been rendered to std-out as a base-64 encoded PNG
+fn write_b64(config: &Config, image_buffer: &RgbImage) -> Result<(), KyrosError> {
     let mut png_buf = Vec::new();
-    {
-        let encoder = PngEncoder::new(&mut png_buf);
-        let _ = encoder.write_image(&image_buffer.clone().into_raw(), image_buffer.width(), image_buffer.height(), image::ColorType::Rgb8);
-    }
+    write_png(config, &mut png_buf, image_buffer, "base-64 output")?;
 
     let mut b64 = String::new();
-    let _ = general_purpose::STANDARD.encode_string(png_buf, &mut b64);
-    print!("{}", b64);
+    general_purpose::STANDARD.encode_string(png_buf, &mut b64);
+    std::io::stdout().write_all(b64.as_bytes()).map_err(|error| KyrosError::io("std-out", error))?;
     Ok(())
 }
 
-const SAVE_METHODS: [(&str, &dyn Fn(image::RgbImage, &Config) -> Result<(), Error>, &str);2] = [
+/// Saves every frame into one looping GIF, each frame gets its own palette of (up to) 256 colors
+fn GIF_FRAMES(config: &Config, frames: u64, frame: &FrameFn) -> Result<(), KyrosError> {
+    let path = config.output_path("gif");
+    let file = File::create(&path).map_err(|error| KyrosError::io(&path, error))?;
+    let image_error = |error| KyrosError::from_image(&path, error);
+
+    // Speed 10 of the NeuQuant quantizer, the same default the gif crate uses
+    let mut encoder = GifEncoder::new_with_speed(BufWriter::new(file), 10);
+    encoder.set_repeat(Repeat::Infinite).map_err(image_error)?;
+    let delay = Delay::from_numer_denom_ms(1000, config.fps.max(1) as u32);
+
+    for index in 0..frames {
+        let image = image::DynamicImage::ImageRgb8(frame(index)?).into_rgba8();
+        encoder.encode_frame(Frame::from_parts(image, 0, 0, delay)).map_err(image_error)?;
+    }
+    return Ok(());
+}
+
+/// Saves every frame into one looping animated PNG, without losing any colors
+fn APNG_FRAMES(config: &Config, frames: u64, frame: &FrameFn) -> Result<(), KyrosError> {
+    let path = config.output_path("png");
+    let file = File::create(&path).map_err(|error| KyrosError::io(&path, error))?;
+    let png_error = |error| KyrosError::from_png(&path, error);
+
+    let mut encoder = png_encoder(config, BufWriter::new(file), config.size_x, config.size_y)?;
+    encoder.set_animated(frames as u32, 0).map_err(png_error)?;
+    encoder.set_frame_delay(1, config.fps.max(1)).map_err(png_error)?;
+
+    let mut writer = encoder.write_header().map_err(png_error)?;
+    for index in 0..frames {
+        writer.write_image_data(&frame(index)?).map_err(png_error)?;
+    }
+    writer.finish().map_err(png_error)?;
+    return Ok(());
+}
+
+fn GIF(config: &Config) -> Result<(), KyrosError> {
+    return GIF_FRAMES(config, 1, &|_| eval_function(config));
+}
+
+fn APNG(config: &Config) -> Result<(), KyrosError> {
+    return APNG_FRAMES(config, 1, &|_| eval_function(config));
+}
+
+/// Type of every save method
+pub type SaveFn = dyn Fn(&Config) -> Result<(), KyrosError>;
+
+pub const SAVE_METHODS: [(&str, &SaveFn, &str);7] = [
     ("PNG", &PNG, "Saves Image as PNG."),
+    ("STREAM", &STREAM, "Saves Image as PNG, rendering and writing it in bands of rows to bound memory use."),
     ("B64", &B64, "Sends base-64 encoded PNG image to std-out."),
+    ("GIF", &GIF, "Saves Image as GIF, animations are saved as one looping GIF."),
+    ("APNG", &APNG, "Saves Image as PNG, animations are saved as one looping animated PNG."),
+    ("KYF", &KYF, "Saves the measured value of every pixel with the config, for the 'recolor' command."),
+    ("NPY", &NPY, "Saves the measured value of every pixel as a NumPy array (planes: value, steps, escaped)."),
+];
+
+/// Type of the function rendering frame `index` of an animation
+pub type FrameFn<'a> = dyn Fn(u64) -> Result<RgbImage, KyrosError> + 'a;
+
+/// Type of every save method that writes all the frames of an animation into one file
+pub type AnimatedSaveFn = dyn Fn(&Config, u64, &FrameFn) -> Result<(), KyrosError>;
+
+pub const ANIMATED_SAVE_METHODS: [(&str, &AnimatedSaveFn, &str);2] = [
+    ("GIF", &GIF_FRAMES, "\tSaves every frame into one looping GIF (with a palette of 256 colors.)"),
+    ("APNG", &APNG_FRAMES, "Saves every frame into one looping animated PNG."),
 ];
 
 
 /// Function for getting the method for saving images from config
-pub fn get_save_method(save_method: &str) -> &dyn Fn(image::RgbImage, &Config) -> Result<(), Error> {
+pub fn get_save_method(save_method: &str) -> Result<&'static SaveFn, KyrosError> {
 
-    // Tries to find function in FORMULAS const
+    // Tries to find function in SAVE_METHODS const
     for (key, value, _) in SAVE_METHODS.iter() {
         if key == &save_method {
-            return value;
+            return Ok(value);
         }
     }
 
-    let saves_string: String = SAVE_METHODS
-        .iter()
-        .map(|v| format!("  {}\t{}", v.0, v.2))
-        .collect::<Vec<String>>()
-        .join("\n");
+    // If not found return error
+    return Err(KyrosError::not_found(
+        "Save method",
+        "save methods",
+        save_method,
+        SAVE_METHODS.iter().map(|v| (v.0, v.2)),
+    ));
+}
+
+/// Function for getting the method for saving animations into one file from config
+pub fn get_animated_save_method(save_method: &str) -> Result<&'static AnimatedSaveFn, KyrosError> {
+
+    // Tries to find function in ANIMATED_SAVE_METHODS const
+    for (key, value, _) in ANIMATED_SAVE_METHODS.iter() {
+        if key == &save_method {
+            return Ok(value);
+        }
+    }
 
-    // If not found throw error
-    Args::command().error(
-        ErrorKind::InvalidValue,
-        format!("Save method '{}' not found!\n\nAllowed save methods:\n{}", save_method, saves_string)
-    ).exit();
+    // If not found return error
+    return Err(KyrosError::not_found(
+        "Animated save method",
+        "animated save methods",
+        save_method,
+        ANIMATED_SAVE_METHODS.iter().map(|v| (v.0, v.2)),
+    ));
+}
+
+/// Checks the save method from config can save an image that has already been rendered,
+/// KYF & NPY need the measured values behind it which aren't kept
+pub fn check_save_image(config: &Config) -> Result<(), KyrosError> {
+    get_save_method(config.save_method.as_str())?;
+    if matches!(config.save_method.as_str(), "KYF" | "NPY") {
+        return Err(KyrosError::InvalidOption(format!(
+            "Save method {} needs the measured values, which this image doesn't keep!", config.save_method
+        )));
+    }
+    return Ok(());
+}
+
+/// Saves an image that has already been rendered (such as a julia grid) with the save method from config
+pub fn save_image(config: &Config, image_buffer: &RgbImage) -> Result<(), KyrosError> {
+    check_save_image(config)?;
+    return match config.save_method.as_str() {
+        "B64" => write_b64(config, image_buffer),
+        "GIF" => GIF_FRAMES(config, 1, &|_| Ok(image_buffer.clone())),
+        "APNG" => APNG_FRAMES(config, 1, &|_| Ok(image_buffer.clone())),
+        _ => save_png(config, image_buffer),
+    };
+}
+
+/// Function for getting where the save method from config writes the image
+pub fn saved_path(config: &Config) -> String {
+    return match config.save_method.as_str() {
+        "B64" => "std-out".to_string(),
+        "GIF" => config.output_path("gif"),
+        "KYF" => config.output_path("kyf"),
+        "NPY" => config.output_path("npy"),
+        _ => config.output_path("png"),
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::temp_path;
+
+    /// Saves the image with `save_method` & reads its pixels back
+    fn saved_pixels(config: &Config, save_method: &str) -> RgbImage {
+        let path = temp_path(&format!("{}-{}", save_method, config.band_rows));
+        let config = Config { save_method: save_method.to_string(), output: Some(path.clone()), ..config.clone() };
+        get_save_method(save_method).unwrap()(&config).unwrap();
+
+        let image = image::open(format!("{}.png", path)).unwrap().to_rgb8();
+        std::fs::remove_file(format!("{}.png", path)).unwrap();
+        return image;
+    }
+
+    #[test]
+    fn stream_matches_png() {
+        let config = Config { size_x: 40, size_y: 50, max_i: 256, measurement: "SMOOTH".to_string(), threads: 3, ..Config::default() };
+        let png = saved_pixels(&config, "PNG");
+        assert!(png.as_raw() == eval_function(&config).unwrap().as_raw());
+
+        // One row at a time, bands that don't divide the height, one band & a band past the end
+        for band_rows in [1, 7, 50, 64] {
+            let stream = saved_pixels(&Config { band_rows, ..config.clone() }, "STREAM");
+            assert_eq!(stream.dimensions(), png.dimensions());
+            assert!(stream.as_raw() == png.as_raw(), "{} band rows", band_rows);
+        }
+    }
 }
@@ -5,10 +5,16 @@ Author : @Some1and2
 File for containing the logic for the Complex Struct
 */
 
-use std::ops::{ Add, Sub, Mul };
+use crate::error::KyrosError;
+use crate::math::measurement::Sample;
+
+use serde::{Deserialize, Serialize};
+
+use std::ops::{ Add, Sub, Mul, Div, Neg };
 
 /// Main object for defining generation configuration. 
-#[derive(Debug, Default)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct Config {
     pub count:                       u64, // Index of the generated image
     pub c_init:          Option<Complex>, // Initial C value for when swap_zc is used
@@ -16,27 +22,212 @@ pub struct Config {
     pub size_y:                      u32, // Sets Image Height
     pub max_i:                       u64, // Sets Maximum Iterations for Generator
     pub gen_formula:              String, // Specifies Formula for Generator
+    pub formula_expr:     Option<String>, // Formula written as an expression, used in place of gen_formula
+    pub polynomial:     Option<Vec<f64>>, // Coefficients (highest power first) of the polynomial of Newton style formulas
     pub color_formula:            String, // Specifies Formula for Colors
+    pub hue_offset:                  f64, // Degrees added to the hue of the color formula
+    pub palette:          Option<String>, // Gradient file used in place of the hue wheel
+    pub palette_offset:              f64, // Fraction of the palette it is moved along by
+    pub palette_scale:       Option<f64>, // Amount of times the palette repeats over the hue wheel (1 when not set)
+    pub normalization:    Option<String>, // Way values are put along colormaps (from NORMALIZATIONS), CYCLE when not set
     pub shadow_formula:           String, // Specifies Formula for Shadows
-    pub travel_distance:            bool, // Speifies if the output color value should be based on travel distance
+    pub measurement:              String, // Specifies Measurement that turns each orbit into a value
+    pub bailout:             Option<f64>, // Escape radius, defaults to the one of the formula
+    pub escape_norm:      Option<String>, // Escape test (from ESCAPE_NORMS), defaults to the one of the formula
+    pub interior:         Option<String>, // Way orbits that never escape are colored (from INTERIORS), BLACK when not set
+    pub instant_escape:   Option<String>, // Color of pixels that escape before the first step (from INSTANT_ESCAPES or #RRGGBB), WHITE when not set
     pub save_method:              String, // Specifies the way the image should be saved
+    pub output:           Option<String>, // Name of the saved file without its extension, {count} is replaced by count
     pub math_frame:            MathFrame,
     pub progress:                   bool,
+    pub threads:                   usize, // Amount of worker threads to render with (0 uses every core)
+    pub band_rows:                   u32, // Amount of rows rendered at a time by streaming save methods
+    pub fps:                         u16, // Frames per second of animated save methods
+    pub deep_zoom:                  bool, // Forces the perturbation renderer even for shallow zooms
 }
 
-/// Struct for factor & offset for math space
+/// Struct for the viewport of the image in math space
 /// This is used to calculate where each pixel is mapped to
-#[derive(Debug, Default)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct MathFrame {
-    pub x_math_space_factor: f64,
-    pub x_math_space_offset: f64,
+    pub center_re: f64, // Real value at the center of the image
+    pub center_im: f64, // Imaginary value at the center of the image
+    pub center_exact: Option<(String, String)>, // Center as typed (re, im), for deep zooms past f64 precision
+    pub zoom:      f64, // Magnification, 1.0 fits [-2, 2] on the shorter side of the image
+    pub rotation:  f64, // Rotation of the viewport around its center in degrees
+}
+
+/// The defaults of the CLI, so the default config renders the same image as `kyros -y`
+impl Default for Config {
+    fn default() -> Config {
+        return Config {
+            count: 0,
+            c_init: None,
+            size_x: 256,
+            size_y: 256,
+            max_i: 1024,
+            gen_formula: "SD".to_string(),
+            formula_expr: None,
+            polynomial: None,
+            color_formula: "ROTATIONAL".to_string(),
+            hue_offset: 0.0,
+            palette: None,
+            palette_offset: 0.0,
+            palette_scale: None,
+            normalization: None,
+            shadow_formula: "NONE".to_string(),
+            measurement: "ITERATIONS".to_string(),
+            bailout: None,
+            escape_norm: None,
+            interior: None,
+            instant_escape: None,
+            save_method: "PNG".to_string(),
+            output: None,
+            math_frame: MathFrame::default(),
+            progress: false,
+            threads: 0,
+            band_rows: 256,
+            fps: 24,
+            deep_zoom: false,
+        };
+    }
+}
+
+/// The [-2, 2] square
+impl Default for MathFrame {
+    fn default() -> MathFrame {
+        return MathFrame {
+            center_re: 0.0,
+            center_im: 0.0,
+            center_exact: None,
+            zoom: 1.0,
+            rotation: 0.0,
+        };
+    }
+}
+
+impl MathFrame {
+    /// Gets the distance in math space between two neighbouring pixels
+    pub fn pixel_size(&self, size_x: u32, size_y: u32) -> f64 {
+        let shorter_side = (size_x.min(size_y) as f64 - 1.0).max(1.0);
+        return 4.0 / (self.zoom * shorter_side);
+    }
+}
+
+impl Config {
+    /// Gets the path the image is saved to, out#{count} unless an output name is set
+    pub fn output_path(&self, extension: &str) -> String {
+        let name = match &self.output {
+            Some(output) => output.replace("{count}", &self.count.to_string()),
+            None => format!("out#{}", self.count),
+        };
+        return format!("{}.{}", name, extension);
+    }
+
+    /// Checks the image size & viewport can be rendered
+    pub fn validate(&self) -> Result<(), KyrosError> {
+        let frame = &self.math_frame;
+        if self.size_x == 0 || self.size_y == 0 {
+            return Err(KyrosError::InvalidGeometry(format!(
+                "Image size {}x{} is invalid, both sides need at least one pixel!", self.size_x, self.size_y
+            )));
+        }
+        // The measured samples are the largest buffer kept for every pixel
+        let bytes = (self.size_x as usize)
+            .checked_mul(self.size_y as usize)
+            .and_then(|pixels| pixels.checked_mul(std::mem::size_of::<Sample>().max(3)));
+        if bytes.is_none_or(|bytes| bytes > isize::MAX as usize) {
+            return Err(KyrosError::InvalidGeometry(format!(
+                "Image size {}x{} is too large to be held in memory!", self.size_x, self.size_y
+            )));
+        }
+        if !(frame.zoom > 0.0 && frame.zoom.is_finite()) {
+            return Err(KyrosError::InvalidGeometry(format!(
+                "Zoom '{}' is invalid, it must be a positive number!", frame.zoom
+            )));
+        }
+        if !(frame.center_re.is_finite() && frame.center_im.is_finite()) {
+            return Err(KyrosError::InvalidGeometry(format!(
+                "Center '{} + {}i' is invalid, both parts must be finite!", frame.center_re, frame.center_im
+            )));
+        }
+        if !frame.rotation.is_finite() {
+            return Err(KyrosError::InvalidGeometry(format!(
+                "Rotation '{}' is invalid, it must be a finite number!", frame.rotation
+            )));
+        }
+        return Ok(());
+    }
+
+    /// Gets the offset of pixel (x, y) from the center of the image,
+    /// measured in pixels along the (rotated) axes of math space
+    pub fn pixel_steps(&self, x: f64, y: f64) -> Complex {
+        let dx = x - (self.size_x as f64 - 1.0) * 0.5;
+        let dy = y - (self.size_y as f64 - 1.0) * 0.5;
+
+        if self.math_frame.rotation == 0.0 {
+            return Complex { real: dx, imaginary: dy };
+        }
+        let (sin, cos) = self.math_frame.rotation.to_radians().sin_cos();
+        return Complex {
+            real      : dx * cos - dy * sin,
+            imaginary : dx * sin + dy * cos,
+        };
+    }
+
+    /// Gets the offset in math space of pixel (x, y) from the center of the image
+    pub fn pixel_offset(&self, x: f64, y: f64) -> Complex {
+        let pixel_size = self.math_frame.pixel_size(self.size_x, self.size_y);
+        let steps = self.pixel_steps(x, y);
+        return Complex {
+            real      : steps.real * pixel_size,
+            imaginary : steps.imaginary * pixel_size,
+        };
+    }
+
+    /// Gets the point in math space pixel (x, y) is mapped to
+    pub fn pixel_to_complex(&self, x: f64, y: f64) -> Complex {
+        let frame = &self.math_frame;
+
+        // Unrotated viewports are mapped as `pixel_size * x - half of the side`, the same way the
+        // [-2, 2] square always was, so the default viewport keeps its pixels
+        if frame.rotation == 0.0 {
+            let pixel_size = frame.pixel_size(self.size_x, self.size_y);
+            let shorter_side = (self.size_x.min(self.size_y) as f64 - 1.0).max(1.0);
+            let half_side = |size: u32| 2.0 * (size as f64 - 1.0) / shorter_side / frame.zoom;
+            return Complex {
+                real      : pixel_size * x - half_side(self.size_x) + frame.center_re,
+                imaginary : pixel_size * y - half_side(self.size_y) + frame.center_im,
+            };
+        }
 
-    pub y_math_space_factor: f64,
-    pub y_math_space_offset: f64,   
+        let center = Complex {
+            real: self.math_frame.center_re,
+            imaginary: self.math_frame.center_im,
+        };
+        return center + self.pixel_offset(x, y);
+    }
+
+    /// Gets the pixel (x, y) a point in math space is mapped to, the inverse of `pixel_to_complex`
+    pub fn complex_to_pixel(&self, point: Complex) -> (f64, f64) {
+        let pixel_size = self.math_frame.pixel_size(self.size_x, self.size_y);
+        let dx = (point.real - self.math_frame.center_re) / pixel_size;
+        let dy = (point.imaginary - self.math_frame.center_im) / pixel_size;
+
+        let (dx, dy) = match self.math_frame.rotation == 0.0 {
+            true => (dx, dy),
+            false => {
+                let (sin, cos) = self.math_frame.rotation.to_radians().sin_cos();
+                (dx * cos + dy * sin, dy * cos - dx * sin)
+            },
+        };
+        return (dx + (self.size_x as f64 - 1.0) * 0.5, dy + (self.size_y as f64 - 1.0) * 0.5);
+    }
 }
 
 // Sets up Complex Struct
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 pub struct Complex {
 	pub real: f64,
 	pub imaginary: f64,
@@ -77,9 +268,160 @@ impl Mul for Complex {
 	}
 }
 
+// Sets up Division rules for Complex Numbers
+impl Div for Complex {
+	type Output = Complex;
+
+	fn div(self, other: Complex) -> Complex {
+		let denominator = other.norm_sqr();
+		Complex {
+			real : (self.real * other.real + self.imaginary * other.imaginary) / denominator,
+			imaginary : (self.imaginary * other.real - self.real * other.imaginary) / denominator,
+		}
+	}
+}
+
+impl Neg for Complex {
+	type Output = Complex;
+
+	fn neg(self) -> Complex {
+		Complex {
+			real : -self.real,
+			imaginary : -self.imaginary,
+		}
+	}
+}
+
 impl Complex {
 	// Sets up Comparison rules for Complex Numbers
 	pub fn is_greater(self, other: f64) -> bool {
 		(self.real * self.real + self.imaginary * self.imaginary) > (other * other)
 	}
+
+	/// Gets the squared absolute value
+	pub fn norm_sqr(self) -> f64 {
+		self.real * self.real + self.imaginary * self.imaginary
+	}
+
+	/// Gets the absolute value
+	pub fn abs(self) -> f64 {
+		self.real.hypot(self.imaginary)
+	}
+
+	/// Gets the angle to the positive real axis
+	pub fn arg(self) -> f64 {
+		self.imaginary.atan2(self.real)
+	}
+
+	pub fn conj(self) -> Complex {
+		Complex { real: self.real, imaginary: -self.imaginary }
+	}
+
+	pub fn exp(self) -> Complex {
+		let (sin, cos) = self.imaginary.sin_cos();
+		let length = self.real.exp();
+		Complex { real: length * cos, imaginary: length * sin }
+	}
+
+	/// Gets the natural logarithm (principal branch)
+	pub fn ln(self) -> Complex {
+		Complex { real: self.abs().ln(), imaginary: self.arg() }
+	}
+
+	/// Raises to a whole power by repeated squaring
+	pub fn powi(self, power: i32) -> Complex {
+		let mut base = if power < 0 { Complex { real: 1.0, imaginary: 0.0 } / self } else { self };
+		let mut power = power.unsigned_abs();
+		let mut out = Complex { real: 1.0, imaginary: 0.0 };
+		while power > 0 {
+			if power & 1 == 1 { out = out * base; }
+			base = base * base;
+			power >>= 1;
+		}
+		out
+	}
+
+	/// Raises to a complex power (principal branch)
+	pub fn powc(self, power: Complex) -> Complex {
+		// z^0 is 1 for every z, including 0
+		if power.real == 0.0 && power.imaginary == 0.0 {
+			return Complex { real: 1.0, imaginary: 0.0 };
+		}
+		// 0^w is only 0 when re w > 0, otherwise it's left to ln(0) to give a non-finite result
+		if self.real == 0.0 && self.imaginary == 0.0 && power.real > 0.0 {
+			return self;
+		}
+		(self.ln() * power).exp()
+	}
+
+	pub fn sqrt(self) -> Complex {
+		let length = self.abs().sqrt();
+		let (sin, cos) = (0.5 * self.arg()).sin_cos();
+		Complex { real: length * cos, imaginary: length * sin }
+	}
+
+	pub fn sin(self) -> Complex {
+		Complex {
+			real : self.real.sin() * self.imaginary.cosh(),
+			imaginary : self.real.cos() * self.imaginary.sinh(),
+		}
+	}
+
+	pub fn cos(self) -> Complex {
+		Complex {
+			real : self.real.cos() * self.imaginary.cosh(),
+			imaginary : -self.real.sin() * self.imaginary.sinh(),
+		}
+	}
+
+	pub fn sinh(self) -> Complex {
+		Complex {
+			real : self.real.sinh() * self.imaginary.cos(),
+			imaginary : self.real.cosh() * self.imaginary.sin(),
+		}
+	}
+
+	pub fn cosh(self) -> Complex {
+		Complex {
+			real : self.real.cosh() * self.imaginary.cos(),
+			imaginary : self.real.sinh() * self.imaginary.sin(),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_viewport_keeps_its_pixels() {
+        let frame = MathFrame { zoom: 1.0, ..MathFrame::default() };
+        let config = Config { size_x: 512, size_y: 512, math_frame: frame, ..Config::default() };
+
+        // The [-2, 2] square is mapped the same way it always was
+        let factor = 4.0 / (512.0 - 1.0);
+        for (x, y) in [(0.0, 0.0), (17.0, 300.0), (255.0, 256.0), (511.0, 511.0)] {
+            let point = config.pixel_to_complex(x, y);
+            assert_eq!(point.real, factor * x - 2.0);
+            assert_eq!(point.imaginary, factor * y - 2.0);
+        }
+    }
+
+    #[test]
+    fn pixels_round_trip() {
+        let frame = MathFrame { center_re: -0.75, center_im: 0.1, zoom: 8.0, ..MathFrame::default() };
+        for rotation in [0.0, 30.0] {
+            let config = Config {
+                size_x: 300,
+                size_y: 200,
+                math_frame: MathFrame { rotation, ..frame.clone() },
+                ..Config::default()
+            };
+            let center = config.pixel_to_complex(149.5, 99.5);
+            assert!((center.real + 0.75).abs() < 1e-12 && (center.imaginary - 0.1).abs() < 1e-12);
+
+            let (x, y) = config.complex_to_pixel(config.pixel_to_complex(12.0, 187.0));
+            assert!((x - 12.0).abs() < 1e-6 && (y - 187.0).abs() < 1e-6);
+        }
+    }
 }
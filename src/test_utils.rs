@@ -0,0 +1,8 @@
+/*
+  Helpers shared by the tests of every module
+*/
+
+/// Gets a path in the temp directory that no other test run uses
+pub fn temp_path(name: &str) -> String {
+    return std::env::temp_dir().join(format!("kyros-test-{}-{}", std::process::id(), name)).to_string_lossy().into_owned();
+}
@@ -1,4 +1,3 @@
-
 /*
 Author : Mark T
   Date : 10/17/2023
@@ -6,21 +5,424 @@ Author : Mark T
   File for general utilities
 */
 
-use super::*;
+use crate::colors::color::{get_color, get_instant_escape, ColorFn, InstantEscape};
+use crate::colors::colormap::{get_colormap, get_normalization, Colormap, Normalization, ValueRange};
+use crate::colors::palette::{get_palette, Palette};
+use crate::colors::shadows::{get_shadow, ShadowFn};
+use crate::error::KyrosError;
+use crate::field::Field;
+use crate::math::expression::get_expression;
+use crate::math::formula::{get_formula, Formula, FormulaDerivativeFn};
+use crate::math::interior::{find_attractor, get_interior, numeric_derivative, InteriorFn, InteriorPass};
+use crate::math::measurement::{get_measurement, DerivativeFn, Measurer, Sample, HUE_CYCLE};
+use crate::math::perturbation::DeepZoom;
+use crate::structs::{Complex, Config};
+
+use rayon::prelude::*;
+use std::io::Write;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// Type of the generator function used by a render, either a named formula or an expression
+type GeneratorFn = dyn Fn(Complex, Complex) -> Complex + Sync + Send;
+
+/// Everything measuring the pixels of an image needs from the config
+pub struct Measuring {
+    generator_function: Arc<GeneratorFn>,
+    measurer: Measurer,
+    start: Option<Complex>,      // Starting z in place of the pixel, outside of julia sets
+    interior: Option<&'static InteriorFn>, // Value of the orbits that never escape, they stay black without one
+    deep_zoom: Option<DeepZoom>, // Perturbation renderer for zooms past f64 precision
+}
+
+impl Measuring {
+    /// Looks up the formula (or compiles the expression) & measurement from config
+    pub fn new(config: &Config) -> Result<Measuring, KyrosError> {
+        config.validate()?;
+
+        let formula = get_formula(config.gen_formula.as_str())?;
+
+        // Uses the formula expression in place of the named formula when one is set,
+        // expressions escape the same way as the mandelbrot
+        let coefficients = polynomial_coefficients(config, formula)?;
+        let (generator_function, degree, bailout, escape_norm): (Arc<GeneratorFn>, f64, f64, &str) = match config.formula_expr.as_deref() {
+            Some(text) => {
+                let expression = get_expression(text)?;
+                let degree = expression.degree();
+                (Arc::new(move |c, z| expression.eval(c, z)), degree, 2.0, "EUCLIDEAN")
+            },
+            None => {
+                // Escaping polynomial formulas (such as MULTIBROT) grow with the degree of their polynomial
+                let degree = match (&coefficients, formula.escape_norm) {
+                    (Some(coefficients), "EUCLIDEAN") => (coefficients.len() - 1) as f64,
+                    _ => formula.degree,
+                };
+                (polynomial_function(formula, coefficients.clone()), degree, formula.bailout, formula.escape_norm)
+            },
+        };
+        let (start, derivative) = match config.formula_expr {
+            Some(_) => (None, None),
+            None => (formula.start, formula.derivative),
+        };
+
+        let mut measurer = Measurer::new(config, degree, bailout, escape_norm)?;
+        if measurer.measurement.uses_derivative {
+            measurer.derivative = Some(derivative_function(config, derivative, coefficients.unwrap_or_default(), generator_function.clone()));
+
+            // Starting points that aren't the pixel don't change with c
+            if config.c_init.is_none() && start.is_some() {
+                measurer.start_dz = Complex { real: 0.0, imaginary: 0.0 };
+            }
+        }
+
+        // Sets up the perturbation renderer for zooms past f64 precision
+        let deep_zoom = DeepZoom::new(config, &measurer)?;
+
+        let interior = get_interior(config.interior.as_deref())?;
+
+        // Past f64 precision the pixels all round to the same c (outside of julia sets), which the
+        // attractor is found with, so only julia sets (with their exact c) get interior colors
+        if deep_zoom.is_some() && interior.is_some() && config.c_init.is_none() {
+            return Err(KyrosError::InvalidOption(format!(
+                "Interior coloring {} doesn't work with deep zooms outside of julia sets, only BLACK does!",
+                config.interior.as_deref().unwrap_or("BLACK")
+            )));
+        }
+
+        return Ok(Measuring { generator_function, measurer, start, interior, deep_zoom });
+    }
+
+    /// Runs the orbit starting at `point` (the c value, or z of julia sets) for up to `max_i` steps,
+    /// with every value it passes through put into `points`. Gets the amount of steps if it escaped.
+    pub fn orbit(&self, config: &Config, point: Complex, max_i: u64, points: &mut Vec<Complex>) -> Option<u64> {
+        let (c, mut z) = match config.c_init {
+            Some(c) => (c, point),
+            None => (point, self.start.unwrap_or(point)),
+        };
+        points.clear();
+
+        let mut orbit = self.measurer.start(c, z);
+        for _ in 0..max_i {
+            if self.measurer.escape(&mut orbit) { break; }
+            z = (self.generator_function)(c, z);
+            self.measurer.step(&mut orbit, z);
+            points.push(z);
+        }
+        return orbit.escaped.then_some(orbit.steps);
+    }
+
+    /// Measures every pixel of row `i` into `samples`
+    pub fn measure_row(&self, config: &Config, i: u32, samples: &mut [Sample]) {
+        // Orbits that never escape are followed to their attractor when the interior is colored
+        let interior_pass = self.interior.map(|interior| {
+            move |c, z| interior(&find_attractor(self.generator_function.as_ref(), c, z))
+        });
+        let interior = interior_pass.as_ref().map(|pass| pass as &InteriorPass);
+
+        match &self.deep_zoom {
+            Some(deep_zoom) => deep_zoom.measure_row(config, &self.measurer, interior, i, samples),
+            None => measure_row(config, self.generator_function.as_ref(), &self.measurer, self.start, interior, i, samples),
+        }
+    }
+}
+
+/// Gets the polynomial of the config for the formulas that take one (such as NEWTON), None for the rest
+fn polynomial_coefficients(config: &Config, formula: &'static Formula) -> Result<Option<Vec<f64>>, KyrosError> {
+    let (Some(coefficients), Some(_)) = (&config.polynomial, formula.polynomial) else {
+        return Ok(None);
+    };
+
+    // Leading zeros don't change the polynomial
+    let coefficients: Vec<f64> = coefficients.iter().copied().skip_while(|v| *v == 0.0).collect();
+    if coefficients.len() < 2 || coefficients.iter().any(|v| !v.is_finite()) {
+        return Err(KyrosError::InvalidOption(format!(
+            "Polynomial {:?} is invalid, it needs finite coefficients & a degree of at least 1!", config.polynomial.as_ref().unwrap()
+        )));
+    }
+    return Ok(Some(coefficients));
+}
+
+/// Gets the generator function of a formula, using the polynomial of the config when it has one
+fn polynomial_function(formula: &'static Formula, coefficients: Option<Vec<f64>>) -> Arc<GeneratorFn> {
+    return match (coefficients, formula.polynomial) {
+        (Some(coefficients), Some(polynomial_function)) => Arc::new(move |c, z| polynomial_function(&coefficients, c, z)),
+        _ => Arc::new(formula.function),
+    };
+}
+
+/// Gets the derivatives of the generator function, for measurements that keep track of dz.
+/// Formulas without analytic ones (& expressions) get them numerically, from the generator function.
+fn derivative_function(
+    config: &Config,
+    derivative: Option<&'static FormulaDerivativeFn>,
+    coefficients: Vec<f64>,
+    generator_function: Arc<GeneratorFn>,
+) -> Box<DerivativeFn> {
+    let zero = Complex { real: 0.0, imaginary: 0.0 };
+
+    // Julia sets take the derivative by the starting z, which c doesn't depend on
+    let julia = config.c_init.is_some();
+
+    return match derivative {
+        Some(derivative) => Box::new(move |c, z| {
+            let (dfdz, dfdc) = derivative(&coefficients, c, z);
+            (dfdz, if julia { zero } else { dfdc })
+        }),
+        None => Box::new(move |c, z| {
+            let dfdz = numeric_derivative(&|z| generator_function(c, z), z);
+            (dfdz, if julia { zero } else { numeric_derivative(&|c| generator_function(c, z), c) })
+        }),
+    };
+}
+
+/// Function for getting image from configuration and generator function.
+/// The image is measured first & then colored.
+pub fn eval_function(config: &Config) -> Result<image::RgbImage, KyrosError> {
+
+    config.validate()?;
+
+    let pool = thread_pool(config)?;
+    let field = measure_field(config, &pool)?;
+    return color_field(config, &pool, &field);
+}
+
+/// Function for getting the worker threads from config (0 lets rayon use every core).
+/// Pools are kept, so every render with the same amount of threads shares one.
+pub fn thread_pool(config: &Config) -> Result<Arc<rayon::ThreadPool>, KyrosError> {
+    static POOLS: Mutex<Vec<(usize, Arc<rayon::ThreadPool>)>> = Mutex::new(Vec::new());
+
+    // A render that panicked while holding the lock can't have left the list half changed
+    let mut pools = POOLS.lock().unwrap_or_else(|error| error.into_inner());
+    if let Some((_, pool)) = pools.iter().find(|(threads, _)| *threads == config.threads) {
+        return Ok(pool.clone());
+    }
+    let pool = Arc::new(rayon::ThreadPoolBuilder::new()
+        .num_threads(config.threads)
+        .build()
+        .map_err(|error| KyrosError::Threads(error.to_string()))?);
+    pools.push((config.threads, pool.clone()));
+    return Ok(pool);
+}
+
+/// Function for showing how many of the rows are done
+fn report_progress(config: &Config, done: u32) {
+    if config.progress {
+        print!("\t {:.2}% | {} / {}\r", 100.0 * done as f64 / config.size_y as f64, done, config.size_y);
+        let _ = std::io::stdout().flush();
+    }
+}
+
+/// Function for rendering a band of rows into raw RGB data.
+/// `band` holds whole rows starting at row `first_row` of the image.
+pub fn eval_rows(config: &Config, measuring: &Measuring, pool: &rayon::ThreadPool, first_row: u32, band: &mut [u8]) -> Result<(), KyrosError> {
+    let mut samples = vec![Sample::default(); band.len() / 3];
+    measure_rows(config, measuring, pool, first_row, &mut samples);
+    return color_rows(config, pool, &samples, band);
+}
+
+/// Function for measuring a band of rows, without coloring them.
+/// `samples` holds whole rows starting at row `first_row` of the image.
+pub fn measure_rows(config: &Config, measuring: &Measuring, pool: &rayon::ThreadPool, first_row: u32, samples: &mut [Sample]) {
+
+    // Amount of rows finished by all the workers
+    let rows_done = AtomicU32::new(first_row);
+
+    // Goes through each row, every worker takes rows as it becomes free
+    pool.install(|| {
+        samples.par_chunks_mut(config.size_x as usize)
+            .enumerate()
+            .for_each(|(i, row)| {
+                measuring.measure_row(config, first_row + i as u32, row);
+                report_progress(config, rows_done.fetch_add(1, Ordering::Relaxed) + 1);
+            });
+    });
+}
+
+/// Where the colors of the pixels come from, a color function (on the hue wheel or a palette) or a colormap
+enum Hue {
+    Function(&'static ColorFn, Option<Palette>),
+    Colormap(&'static Colormap),
+}
+
+/// Everything coloring the pixels of an image needs from the config
+struct Coloring {
+    hue: Hue,
+    normalization: &'static Normalization,
+    range: ValueRange, // Values of the whole image, only collected for the normalizations that need them
+    shadow_function: &'static ShadowFn,
+    hue_offset: f64,
+    interior: bool,                // Whether the orbits that never escaped were given values to color
+    measured_interior: bool,       // Whether the orbits that never escaped are colored like escaped ones, by their measured value
+    max_i: u64,                    // Iterations of the render, the value measured orbits that are left black have
+    instant_escape: InstantEscape, // Color of the pixels that escaped before the first step
+}
+
+impl Coloring {
+    /// Looks up the color (or colormap) & shadow from config, `samples` are every sample of the image.
+    /// Normalizations of the whole image take a first pass over the samples to collect their values.
+    fn new(config: &Config, samples: &[Sample]) -> Result<Coloring, KyrosError> {
+        let shadow_function = get_shadow(config.shadow_formula.as_str())?;
+        let normalization = get_normalization(config.normalization.as_deref())?;
+
+        let hue = match get_colormap(config.color_formula.as_str()) {
+            Ok(colormap) => Hue::Colormap(colormap),
+            Err(_) => Hue::Function(get_color(config.color_formula.as_str())?, get_palette(config)?),
+        };
+
+        // Measurements such as TRAVEL_DISTANCE color the orbits that never escaped like the rest,
+        // unless an interior coloring gives them values of its own
+        let interior = get_interior(config.interior.as_deref())?.is_some();
+        let measured_interior = !interior && get_measurement(config.measurement.as_str())?.colors_interior;
+
+        let range = match normalization.whole_image {
+            true => {
+                if samples.len() < config.size_x as usize * config.size_y as usize {
+                    check_band_coloring(config)?;
+                }
+                ValueRange::new(samples.iter().filter(|v| (v.escaped || measured_interior) && v.steps > 0).map(|v| v.value))
+            },
+            false => ValueRange::default(),
+        };
+        return Ok(Coloring {
+            hue,
+            normalization,
+            range,
+            shadow_function,
+            hue_offset: config.hue_offset,
+            interior,
+            measured_interior,
+            max_i: config.max_i,
+            instant_escape: get_instant_escape(config.instant_escape.as_deref())?,
+        });
+    }
+
+    /// Gets the color of a sample
+    fn color(&self, sample: &Sample) -> (u8, u8, u8) {
+        // Measurements that color the interior are told apart by their value, the way they always were,
+        // 0 is an instant escape & max_i an orbit that is left black
+        let (instant, black) = match self.measured_interior {
+            true => (sample.value == 0.0, sample.value == self.max_i as f64),
+            false => (sample.steps == 0, !sample.escaped && !self.interior),
+        };
+
+        if instant {
+            if let InstantEscape::Rgb(r, g, b) = self.instant_escape {
+                return (r, g, b);
+            }
+        }
+        else if black {
+            return (0, 0, 0);
+        }
+
+        // Values no color can be worked out from (such as the infinite ones of orbits that blew up)
+        // are painted like instant escapes when they escaped & like the interior when they didn't
+        let escaped = sample.escaped || self.measured_interior;
+        return self.color_value(sample, escaped).unwrap_or(match (escaped, self.instant_escape) {
+            (true, InstantEscape::Rgb(r, g, b)) => (r, g, b),
+            _ => (0, 0, 0),
+        });
+    }
+
+    /// Gets the color of the value of a sample, only escaped samples are normalized by the values of the image.
+    /// None when the value (or its shadow) isn't finite.
+    fn color_value(&self, sample: &Sample, escaped: bool) -> Option<(u8, u8, u8)> {
+        let shadow = (self.shadow_function)(sample).rem_euclid(360.0);
+        let stretched = escaped && self.normalization.whole_image;
+        if !(sample.value.is_finite() && shadow.is_finite()) {
+            return None;
+        }
+
+        return match &self.hue {
+            Hue::Function(color_function, palette) => {
+                // Normalized values are passed on as one turn of ROTATIONAL, CYCLE leaves them as they are
+                let value = match stretched {
+                    true => HUE_CYCLE * (self.normalization.function)(sample.value, &self.range),
+                    false => sample.value,
+                };
+                let hue = (color_function(value) + self.hue_offset).rem_euclid(360.0);
+                if !hue.is_finite() {
+                    return None;
+                }
+
+                // Palettes take the place of the hue wheel
+                match palette {
+                    Some(palette) => Some(palette.color(hue / 360.0, shadow)),
+                    None => Some(hsv::hsv_to_rgb(hue, 1.0, shadow)),
+                }
+            },
+            Hue::Colormap(colormap) => {
+                let position = match stretched {
+                    true => (self.normalization.function)(sample.value, &self.range),
+                    false => sample.value / HUE_CYCLE,
+                } + self.hue_offset / 360.0;
+                position.is_finite().then(|| colormap.color(position, !stretched, shadow))
+            },
+        };
+    }
+}
 
-/// Function for getting image from configuration and generator function. 
-pub fn eval_function(config: &Config) -> image::RgbImage {
+/// Checks the colors set in config can be worked out a band of rows at a time,
+/// the normalizations that need the values of the whole image can't be
+pub fn check_band_coloring(config: &Config) -> Result<(), KyrosError> {
+    let normalization = get_normalization(config.normalization.as_deref())?;
+    if normalization.whole_image {
+        return Err(KyrosError::InvalidOption(format!(
+            "Normalization {} needs the whole image, it can't be used while rendering in bands!", normalization.name
+        )));
+    }
+    return Ok(());
+}
 
-    // Unpacks Image Configuration
-    let c_init: Option<Complex> = config.c_init;
+/// Function for coloring measured rows into raw RGB data
+pub fn color_rows(config: &Config, pool: &rayon::ThreadPool, samples: &[Sample], band: &mut [u8]) -> Result<(), KyrosError> {
+
+    let coloring = Coloring::new(config, samples)?;
+
+    let row_length = 3 * config.size_x as usize;
+
+    pool.install(|| {
+        band.par_chunks_mut(row_length)
+            .zip(samples.par_chunks(config.size_x as usize))
+            .for_each(|(row, samples)| color_row(&coloring, samples, row));
+    });
+    return Ok(());
+}
 
-    let generator_function = get_formula(&config.gen_formula.as_str());
-    let color_function = get_color(&config.color_formula.as_str());
-    let shadow_function = get_shadow(&config.shadow_formula.as_str());
+/// Function for measuring every pixel of the image into a field
+pub fn measure_field(config: &Config, pool: &rayon::ThreadPool) -> Result<Field, KyrosError> {
+    let measuring = Measuring::new(config)?;
+    let mut field = Field::new(config.size_x, config.size_y);
+    measure_rows(config, &measuring, pool, 0, &mut field.samples);
+
+    if config.progress {
+        println!();
+    }
+    return Ok(field);
+}
+
+/// Function for coloring a field into an image, with the colors set in config
+pub fn color_field(config: &Config, pool: &rayon::ThreadPool, field: &Field) -> Result<image::RgbImage, KyrosError> {
+    let mut img: image::RgbImage = image::ImageBuffer::new(field.size_x, field.size_y);
+    let config = Config { size_x: field.size_x, size_y: field.size_y, ..config.clone() };
+    color_rows(&config, pool, &field.samples, &mut img)?;
+    return Ok(img);
+}
+
+/// Function for measuring a single row of the image.
+/// `samples` gets the measured value of every pixel in row `i`.
+fn measure_row(
+    config: &Config,
+    generator_function: &GeneratorFn,
+    measurer: &Measurer,
+    start: Option<Complex>,
+    interior: Option<&InteriorPass>,
+    i: u32,
+    samples: &mut [Sample],
+) {
 
     // Sets Initial 'c' Value (If set)
     let mut c = Complex { real: 0f64, imaginary: 0f64, };
-    let is_julia: bool = match c_init {
+    let is_julia: bool = match config.c_init {
         Some(value) => {
             c = value;
             true
@@ -28,76 +430,212 @@ pub fn eval_function(config: &Config) -> image::RgbImage {
         None => false,
     };
 
-    // Sets Math Values
-    let x_math_space_factor = config.math_frame.x_math_space_factor;
-    let x_math_space_offset = config.math_frame.x_math_space_offset;
+    let mut z: Complex;
 
-    let y_math_space_factor = config.math_frame.y_math_space_factor;
-    let y_math_space_offset = config.math_frame.y_math_space_offset;
+    for (j, sample) in samples.iter_mut().enumerate() {
 
-    let mut z: Complex;
-    let mut old_z: Complex;
+         // Sets Initial Z Value
+        z = config.pixel_to_complex(j as f64, i as f64);
 
-    // Initializes Image Buffer
-    let mut img = image::ImageBuffer::new(config.size_x, config.size_y);
+        if !is_julia {
+            c = z;
+            z = start.unwrap_or(z);
+        }
 
-    // Goes through each pixel
-    for i in 0..config.size_y {
-        for j in 0..config.size_x {
+        let mut orbit = measurer.start(c, z);
 
-             // Sets Initial Z Value
-            z = Complex {
-                real      : x_math_space_factor * j as f64 - x_math_space_offset,
-                imaginary : y_math_space_factor * i as f64 - y_math_space_offset,
-            };
-            old_z = z;
+        // Runs Math
+        for iteration in 0..config.max_i {
+            if iteration == config.max_i { break; }
+            if measurer.escape(&mut orbit) { break; }
+            z = generator_function(c, z);
 
-            if is_julia == false { c = z; }
+            // Calculates Output
+            measurer.step(&mut orbit, z);
+        };
 
-            let mut z_output: f64 = 0.0;
+        *sample = measurer.finish_interior(&orbit, c, interior);
+    }
+}
 
-            // Runs Math
-            for iteration in 0..config.max_i {
-                if iteration == config.max_i { break; }
+/// Function for coloring a row of samples into raw RGB data
+fn color_row(coloring: &Coloring, samples: &[Sample], row: &mut [u8]) {
+    for (sample, pixel) in samples.iter().zip(row.chunks_exact_mut(3)) {
+        let out_rgb = coloring.color(sample);
+        pixel.copy_from_slice(&[out_rgb.0, out_rgb.1, out_rgb.2]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::structs::MathFrame;
+
+    fn test_config(formula: &str, measurement: &str, size: u32) -> Config {
+        return Config {
+            size_x: size,
+            size_y: size,
+            max_i: 256,
+            gen_formula: formula.to_string(),
+            color_formula: "ROTATIONAL".to_string(),
+            shadow_formula: "MINIMAL".to_string(),
+            measurement: measurement.to_string(),
+            save_method: "PNG".to_string(),
+            math_frame: MathFrame { zoom: 1.0, ..MathFrame::default() },
+            threads: 1,
+            ..Config::default()
+        };
+    }
+
+    /// Renders the image the way it was before the measurements were split from the coloring,
+    /// with the escape radius of 2, white instant escapes & black orbits that reach max_i
+    fn golden_image(config: &Config, travel_distance: bool) -> image::RgbImage {
+        let generator_function = get_formula(config.gen_formula.as_str()).unwrap().function;
+        let color_function = get_color(config.color_formula.as_str()).unwrap();
+        let shadow_function = get_shadow(config.shadow_formula.as_str()).unwrap();
+        let factor = 4.0 / (config.size_x as f64 - 1.0);
+
+        return image::ImageBuffer::from_fn(config.size_x, config.size_y, |j, i| {
+            let mut z = Complex { real: factor * j as f64 - 2.0, imaginary: factor * i as f64 - 2.0 };
+            let mut old_z = z;
+            let c = config.c_init.unwrap_or(z);
+
+            let mut z_output: f64 = 0.0;
+            for _ in 0..config.max_i {
                 if z.is_greater(2.0) { break; }
                 z = generator_function(c, z);
-
-                // Calculates Output
-                if !config.travel_distance {
-                    z_output += 1.0;
-                }
-                else {
-                    z_output += (
+                z_output += match travel_distance {
+                    true => (
                         (z.real - old_z.real) * (z.real - old_z.real) +
                         (z.imaginary - old_z.imaginary) * (z.imaginary - old_z.imaginary)
-                    ).sqrt();
-                    old_z = z;
-                }
+                    ).sqrt(),
+                    false => 1.0,
+                };
+                old_z = z;
+            }
+
+            let sample = Sample { value: z_output, ..Sample::default() };
+            let (r, g, b) = match z_output {
+                0.0 => (255, 255, 255),
+                v if v == config.max_i as f64 => (0, 0, 0),
+                v => hsv::hsv_to_rgb(color_function(v).rem_euclid(360.0), 1.0, shadow_function(&sample).rem_euclid(360.0)),
             };
+            image::Rgb([r, g, b])
+        });
+    }
+
+    #[test]
+    fn existing_modes_keep_their_pixels() {
+        for formula in ["SD", "R", "ABR", "BS", "SYM"] {
+            for (measurement, travel_distance) in [("ITERATIONS", false), ("TRAVEL_DISTANCE", true)] {
+                // Odd sizes have pixels right on 0 & -1, which only escape or reach max_i by their value
+                for (size, c_init) in [(65, None), (64, Some(Complex { real: 0.08, imaginary: -0.63 }))] {
+                    let config = Config { c_init, ..test_config(formula, measurement, size) };
+                    let image = eval_function(&config).unwrap();
+                    assert!(image == golden_image(&config, travel_distance), "{} {} {:?}", formula, measurement, c_init);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn every_formula_renders_with_every_measurement() {
+        use crate::math::formula::FORMULAS;
+        use crate::math::measurement::{ESCAPE_NORMS, MEASUREMENTS};
 
-            // Gets pixel pointer
-            let pixel = img.get_pixel_mut(j, i);
-
-            // Sets Pixel Value
-            let out_rgb: (u8, u8, u8);
-            if z_output == 0.0 {out_rgb = (255, 255, 255)}
-            else if z_output == config.max_i as f64 {out_rgb = (0, 0, 0)}
-            else {
-                out_rgb = hsv::hsv_to_rgb(
-                    color_function(z_output).rem_euclid(360.0),
-                    1.0,
-                    shadow_function(z_output).rem_euclid(360.0)
-                );
+        // Every escape norm, with instant escapes colored by their value too
+        let escape_norms: Vec<Option<&str>> = [None].into_iter().chain(ESCAPE_NORMS.iter().map(|v| Some(v.0))).collect();
+        let mut failed = Vec::new();
+        for formula in FORMULAS.iter() {
+            for measurement in MEASUREMENTS.iter() {
+                for (escape_norm, instant_escape) in escape_norms.iter().flat_map(|v| [(v, None), (v, Some("COLOR"))]) {
+                    let config = Config {
+                        max_i: 64,
+                        escape_norm: escape_norm.map(str::to_string),
+                        instant_escape: instant_escape.map(str::to_string),
+                        ..test_config(formula.name, measurement.name, 24)
+                    };
+                    let result = std::panic::catch_unwind(|| eval_function(&config));
+                    if !matches!(result, Ok(Ok(_))) {
+                        failed.push((formula.name, measurement.name, escape_norm, instant_escape));
+                    }
+                }
+            }
+        }
+        assert!(failed.is_empty(), "{:?}", failed);
+    }
+
+    #[test]
+    fn thread_counts_give_the_same_image() {
+        let base = Config { max_i: 512, ..test_config("SD", "SMOOTH", 97) };
+        let configs = [
+            base.clone(),
+            Config { c_init: Some(Complex { real: -0.8, imaginary: 0.156 }), ..base.clone() },
+            Config { normalization: Some("HISTOGRAM".to_string()), interior: Some("PERIOD".to_string()), ..base.clone() },
+            Config { deep_zoom: true, ..base.clone() },
+        ];
+        for config in configs {
+            let single = eval_function(&config).unwrap();
+            for threads in [2, 3, 8] {
+                let multi = eval_function(&Config { threads, ..config.clone() }).unwrap();
+                assert!(single.as_raw() == multi.as_raw(), "{} threads, {:?}", threads, config);
+            }
+        }
+    }
+
+    #[test]
+    fn distances_match_known_points() {
+        use crate::math::measurement::distance_in_pixels;
+
+        // The set lies within |c| <= 2 & reaches -2, so points left of it on the real axis are |c| - 2 away.
+        // Close to the set the estimate is within a factor of 2 of the real distance (by the Koebe quarter theorem)
+        for re in [-2.1, -2.5, -3.0] {
+            let config = Config {
+                max_i: 1000,
+                math_frame: MathFrame { center_re: re, center_im: 0.0, zoom: 1.0, ..MathFrame::default() },
+                ..test_config("SD", "DISTANCE_RAW", 1)
             };
-            *pixel = image::Rgb([out_rgb.0, out_rgb.1, out_rgb.2]);
+            let distance = measure_field(&config, &thread_pool(&config).unwrap()).unwrap().samples[0].value;
+            let real_distance = re.abs() - 2.0;
+            assert!(distance > 0.5 * real_distance && distance < 2.0 * real_distance, "{} at {}", distance, re);
+
+            // DISTANCE gives the same distance in pixels
+            let config = Config { measurement: "DISTANCE".to_string(), ..config };
+            let pixels = distance_in_pixels(measure_field(&config, &thread_pool(&config).unwrap()).unwrap().samples[0].value);
+            let pixel_size = config.math_frame.pixel_size(config.size_x, config.size_y);
+            assert!((pixels * pixel_size - distance).abs() < 1e-9 * distance, "{} at {}", pixels, re);
         }
-        if config.progress {
-            print!("\t {:.2}% | {} / {}\r", 100.0 * (i as f64 + 1.0) / config.size_y as f64, i+1, config.size_y);
+    }
+
+    #[test]
+    fn numeric_derivatives_match_analytic_ones() {
+        // Formula expressions take their derivatives numerically
+        for (formula, expression) in [("SD", "z^2 + c"), ("SYM", "z^2 + c - z"), ("MULTIBROT", "z^3 + c")] {
+            for c_init in [None, Some(Complex { real: -0.4, imaginary: 0.6 })] {
+                let config = Config { c_init, ..test_config(formula, "DISTANCE_RAW", 33) };
+                let numeric_config = Config { formula_expr: Some(expression.to_string()), ..config.clone() };
+                let analytic = measure_field(&config, &thread_pool(&config).unwrap()).unwrap();
+                let numeric = measure_field(&numeric_config, &thread_pool(&config).unwrap()).unwrap();
+
+                let mut compared = 0;
+                for (a, b) in analytic.samples.iter().zip(numeric.samples.iter()) {
+                    assert_eq!((a.steps, a.escaped), (b.steps, b.escaped), "{} {:?}", formula, c_init);
+                    if a.escaped && a.steps > 1 {
+                        compared += 1;
+                        assert!((a.value - b.value).abs() <= 1e-6 * a.value, "{} {:?}: {} & {}", formula, c_init, a.value, b.value);
+                    }
+                }
+                assert!(compared > 100, "{} {:?}", formula, c_init);
+            }
         }
     }
-    if config.progress {
-        println!();
+
+    #[test]
+    fn thread_pools_are_shared() {
+        let config = Config { threads: 3, ..Config::default() };
+        let pool = thread_pool(&config).unwrap();
+        assert_eq!(pool.current_num_threads(), 3);
+        assert!(Arc::ptr_eq(&pool, &thread_pool(&config).unwrap()));
+        assert!(!Arc::ptr_eq(&pool, &thread_pool(&Config { threads: 2, ..config }).unwrap()));
     }
-    return img;
 }
-